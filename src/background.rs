@@ -0,0 +1,330 @@
+//! Writing the lines by a background thread.
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// What the logger does when the queue of the background writer is full, see
+/// [Builder::background](crate::Builder::background).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Waits until the writer makes room for the line.
+    #[default]
+    Block,
+    /// Drops the line being logged.
+    DropNewest,
+    /// Drops the oldest line in the queue to make room for the line being logged.
+    DropOldest,
+}
+
+/// The output a queued line is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Output {
+    Info,
+    Error,
+    Verbose,
+}
+
+struct State {
+    queue: VecDeque<(Output, String)>,
+    dropped: u64,
+    writing: bool,
+    closed: bool,
+    // The thread has exited.
+    stopped: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A bounded queue of lines and the thread writing them.
+///
+/// The dropped lines are reported by a warning line written to the error output before the next lines.
+/// A line whose write panics is lost, the thread goes on with the next ones. If the thread exits anyway,
+/// the lines are handed back to be written directly instead of waiting for it.
+pub(crate) struct Background {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Background {
+    /// Spawns the thread writing the queued lines by `write`.
+    pub(crate) fn spawn<F>(capacity: usize, policy: OverflowPolicy, mut write: F) -> Background
+    where
+        F: FnMut(Output, &str) + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                dropped: 0,
+                writing: false,
+                closed: false,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("afch-logger".to_string())
+                .spawn(move || {
+                    let _stopped = Stopped(&shared);
+                    // A panicking sink loses its line, not the thread.
+                    let mut write = |output, line: &str| {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| write(output, line)));
+                    };
+                    loop {
+                        let mut state = shared.lock();
+                        while state.queue.is_empty() && state.dropped == 0 && !state.closed {
+                            state = shared.wait(state);
+                        }
+                        if state.queue.is_empty() && state.dropped == 0 {
+                            break;
+                        }
+                        let dropped = std::mem::take(&mut state.dropped);
+                        let lines = std::mem::take(&mut state.queue);
+                        state.writing = true;
+                        drop(state);
+                        shared.changed.notify_all();
+
+                        if dropped > 0 {
+                            write(Output::Error, &dropped_summary(dropped));
+                        }
+                        for (output, line) in lines {
+                            write(output, &line);
+                        }
+
+                        shared.lock().writing = false;
+                        shared.changed.notify_all();
+                    }
+                })
+                .expect("Failed to spawn the log writer thread")
+        };
+        Background {
+            shared,
+            capacity: capacity.max(1),
+            policy,
+            thread: Mutex::new(Some(thread)),
+        }
+    }
+
+    /// Queues `line` to be written to `output`, following the overflow policy if the queue is full.
+    /// Returns the line back if the writer is closed or its thread has exited.
+    pub(crate) fn push(&self, output: Output, line: String) -> Result<(), String> {
+        let mut state = self.shared.lock();
+        loop {
+            if state.closed || state.stopped {
+                return Err(line);
+            }
+            if state.queue.len() < self.capacity {
+                break;
+            }
+            match self.policy {
+                OverflowPolicy::Block => state = self.shared.wait(state),
+                OverflowPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return Ok(());
+                }
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+            }
+        }
+        state.queue.push_back((output, line));
+        drop(state);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Waits until all the queued lines are written, or the thread has exited.
+    pub(crate) fn drain(&self) {
+        let mut state = self.shared.lock();
+        while !state.stopped && (!state.queue.is_empty() || state.dropped > 0 || state.writing) {
+            state = self.shared.wait(state);
+        }
+    }
+
+    /// Writes the queued lines and stops the thread. The lines pushed afterwards are returned back.
+    pub(crate) fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        let thread = self
+            .thread
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+// Marks the thread stopped however it exits, waking up the threads waiting for it.
+struct Stopped<'a>(&'a Shared);
+
+impl Drop for Stopped<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.stopped = true;
+        state.writing = false;
+        drop(state);
+        self.0.changed.notify_all();
+    }
+}
+
+fn dropped_summary(dropped: u64) -> String {
+    if dropped == 1 {
+        "warning: 1 log record dropped".to_string()
+    } else {
+        format!("warning: {} log records dropped", dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    type Lines = Arc<Mutex<Vec<String>>>;
+
+    fn collecting() -> (Lines, impl FnMut(Output, &str) + Send) {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let written = lines.clone();
+        (lines, move |output: Output, line: &str| {
+            written
+                .lock()
+                .unwrap()
+                .push(format!("{:?}: {}", output, line))
+        })
+    }
+
+    #[test]
+    fn drain_writes_in_order() {
+        let (lines, write) = collecting();
+        let background = Background::spawn(4, OverflowPolicy::Block, write);
+        for i in 0..100 {
+            background.push(Output::Info, i.to_string()).unwrap();
+        }
+        background.push(Output::Error, "error".to_string()).unwrap();
+        background.drain();
+        let expected: Vec<_> = (0..100)
+            .map(|i| format!("Info: {}", i))
+            .chain(["Error: error".to_string()])
+            .collect();
+        assert_eq!(*lines.lock().unwrap(), expected);
+    }
+
+    // Blocks the writer on the first line until released, so that the queue fills up.
+    fn stalled(policy: OverflowPolicy) -> (Background, Arc<Mutex<Vec<String>>>, mpsc::Sender<()>) {
+        let (lines, mut write) = collecting();
+        let (release, released) = mpsc::channel();
+        let (started, wait_started) = mpsc::channel();
+        let background = Background::spawn(2, policy, move |output, line| {
+            if line == "first" {
+                started.send(()).unwrap();
+                released.recv().unwrap();
+            }
+            write(output, line)
+        });
+        background.push(Output::Info, "first".to_string()).unwrap();
+        wait_started.recv().unwrap();
+        (background, lines, release)
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (background, lines, release) = stalled(OverflowPolicy::DropNewest);
+        for line in ["a", "b", "c", "d"] {
+            background.push(Output::Info, line.to_string()).unwrap();
+        }
+        release.send(()).unwrap();
+        background.drain();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [
+                "Info: first",
+                "Error: warning: 2 log records dropped",
+                "Info: a",
+                "Info: b"
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (background, lines, release) = stalled(OverflowPolicy::DropOldest);
+        for line in ["a", "b", "c", "d", "e"] {
+            background.push(Output::Info, line.to_string()).unwrap();
+        }
+        release.send(()).unwrap();
+        background.drain();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            [
+                "Info: first",
+                "Error: warning: 3 log records dropped",
+                "Info: d",
+                "Info: e"
+            ]
+        );
+    }
+
+    #[test]
+    fn panicking_write() {
+        let (lines, mut write) = collecting();
+        let background = Background::spawn(2, OverflowPolicy::Block, move |output, line| {
+            if line == "panic" {
+                panic!("sink failed");
+            }
+            write(output, line)
+        });
+        for line in ["a", "panic", "b", "panic", "c", "d", "e"] {
+            background.push(Output::Info, line.to_string()).unwrap();
+        }
+        background.drain();
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["Info: a", "Info: b", "Info: c", "Info: d", "Info: e"]
+        );
+    }
+
+    #[test]
+    fn close_writes_the_rest() {
+        let (lines, write) = collecting();
+        let background = Background::spawn(16, OverflowPolicy::Block, write);
+        for i in 0..10 {
+            background.push(Output::Verbose, i.to_string()).unwrap();
+        }
+        background.close();
+        assert_eq!(lines.lock().unwrap().len(), 10);
+        assert_eq!(
+            background.push(Output::Info, "late".to_string()),
+            Err("late".to_string())
+        );
+    }
+}
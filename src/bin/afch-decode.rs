@@ -0,0 +1,356 @@
+//! Restores the error logs encoded by the transforms of `afch_logger` in logs exported from Application
+//! Insights, and prints every log with its real level. The envelopes of `afch_logger::EnvelopeTransform`
+//! are printed with the target, location and time of their records.
+//!
+//! ```text
+//! afch-decode [--format text|csv|json] [--sort] [FILE]
+//! ```
+//!
+//! It reads from stdin if `FILE` is not given. Without `--format`, the format is guessed by the extension of
+//! `FILE`, `.csv` for CSV, `.json`, `.jsonl` and `.ndjson` for JSON, otherwise plain text with one log per
+//! line. For CSV and JSON, the `message` column or field is decoded, and `timestamp` and `severityLevel`
+//! are printed if present. JSON can be an array of objects or one object per line. In plain text, the
+//! encoded logs may follow a timestamp or level, which is kept.
+//!
+//! The `afch_logger::SequenceKey`s prefixed to the logs are kept in the output. With `--sort`, the logs are
+//! sorted by them.
+use std::error::Error;
+use std::io::{self, BufWriter, Read, Write};
+
+use afch_logger::{decode, SequenceKey};
+use serde_json::Value;
+
+const USAGE: &str = "usage: afch-decode [--format text|csv|json] [--sort] [FILE]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    timestamp: Option<String>,
+    level: Option<String>,
+    message: String,
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("afch-decode: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut format = None;
+    let mut sort = false;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = Some(match args.next().as_deref() {
+                    Some("text") => Format::Text,
+                    Some("csv") => Format::Csv,
+                    Some("json") => Format::Json,
+                    _ => return Err(USAGE.into()),
+                })
+            }
+            "--sort" => sort = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let format = format.unwrap_or_else(|| guess_format(path.as_deref()));
+    let mut input = String::new();
+    match &path {
+        Some(path) => input = std::fs::read_to_string(path)?,
+        None => {
+            io::stdin().read_to_string(&mut input)?;
+        }
+    }
+
+    let mut entries = match format {
+        Format::Text => read_text(&input),
+        Format::Csv => read_csv(&input)?,
+        Format::Json => read_json(&input)?,
+    };
+    if sort {
+        afch_logger::sort_by_sequence(&mut entries, |entry| &entry.message);
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    write_entries(&mut out, entries)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn write_entries(out: &mut impl Write, entries: Vec<Entry>) -> io::Result<()> {
+    let mut next_is_error = false;
+    for mut entry in entries {
+        let key = match SequenceKey::parse(&entry.message) {
+            Some((key, message)) => {
+                entry.message = message.to_string();
+                Some(key)
+            }
+            None => None,
+        };
+        if decode::is_as_warning_notice(&entry.message) {
+            next_is_error = true;
+            continue;
+        }
+        if let Some(Ok(envelope)) = decode::decode_envelope(&entry.message) {
+            next_is_error = false;
+            if let Some(timestamp) = &entry.timestamp {
+                write!(out, "{} ", timestamp)?;
+            }
+            if let Some(key) = key {
+                write!(out, "{} ", key)?;
+            }
+            // The text before the envelope, such as the timestamp of a console capture.
+            let start = [decode::ENVELOPE_BASE64_PREFIX, decode::ENVELOPE_HEX_PREFIX]
+                .iter()
+                .filter_map(|prefix| entry.message.find(prefix))
+                .min()
+                .unwrap_or(0);
+            write!(
+                out,
+                "{}[{}] {}",
+                &entry.message[..start],
+                envelope.level,
+                envelope.target
+            )?;
+            if let (Some(file), Some(line)) = (&envelope.file, envelope.line) {
+                write!(out, " ({}:{})", file, line)?;
+            }
+            writeln!(
+                out,
+                " @{}ms: {}",
+                envelope.timestamp_millis, envelope.message
+            )?;
+            continue;
+        }
+        let (level, message) = match decode::decode_line(&entry.message) {
+            Some(Ok(message)) => (Some("Error".to_string()), message),
+            Some(Err(e)) => {
+                eprintln!("afch-decode: failed to decode `{}`: {}", entry.message, e);
+                (entry.level, entry.message)
+            }
+            None if next_is_error => (Some("Error".to_string()), entry.message),
+            None => (entry.level, entry.message),
+        };
+        next_is_error = false;
+
+        if let Some(timestamp) = entry.timestamp {
+            write!(out, "{} ", timestamp)?;
+        }
+        if let Some(key) = key {
+            write!(out, "{} ", key)?;
+        }
+        if let Some(level) = level {
+            write!(out, "[{}] ", level)?;
+        }
+        writeln!(out, "{}", message)?;
+    }
+    Ok(())
+}
+
+fn guess_format(path: Option<&str>) -> Format {
+    let extension = path
+        .and_then(|path| path.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("csv") => Format::Csv,
+        Some("json" | "jsonl" | "ndjson") => Format::Json,
+        _ => Format::Text,
+    }
+}
+
+fn read_text(input: &str) -> Vec<Entry> {
+    input
+        .lines()
+        .map(|line| Entry {
+            timestamp: None,
+            level: None,
+            message: line.to_string(),
+        })
+        .collect()
+}
+
+fn read_csv(input: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |matches: &dyn Fn(&str) -> bool| {
+        headers
+            .iter()
+            .position(|header| matches(&header.trim().to_ascii_lowercase()))
+    };
+    let message = column(&|h| h == "message").ok_or("no `message` column in the CSV header")?;
+    let timestamp = column(&|h| h.starts_with("timestamp"));
+    let severity = column(&|h| h == "severitylevel");
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let field = |index: Option<usize>| index.and_then(|i| record.get(i)).map(str::to_string);
+        entries.push(Entry {
+            timestamp: field(timestamp),
+            level: field(severity).and_then(|s| severity_name(&Value::String(s))),
+            message: field(Some(message)).unwrap_or_default(),
+        });
+    }
+    Ok(entries)
+}
+
+fn read_json(input: &str) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let values: Vec<Value> = if input.trim_start().starts_with('[') {
+        serde_json::from_str(input)?
+    } else {
+        input
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?
+    };
+
+    Ok(values
+        .iter()
+        .filter_map(|value| {
+            let field = |name: &str| {
+                value
+                    .as_object()?
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value)
+            };
+            Some(Entry {
+                timestamp: field("timestamp").map(|t| match t {
+                    Value::String(t) => t.clone(),
+                    t => t.to_string(),
+                }),
+                level: field("severityLevel").and_then(severity_name),
+                message: field("message")?.as_str()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// The name of an Application Insights severity level.
+fn severity_name(severity: &Value) -> Option<String> {
+    let severity = match severity {
+        Value::Number(n) => n.as_u64()?,
+        Value::String(s) => s.trim().parse().ok()?,
+        _ => return None,
+    };
+    let name = match severity {
+        0 => "Verbose",
+        1 => "Information",
+        2 => "Warning",
+        3 => "Error",
+        4 => "Critical",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: Option<&str>, level: Option<&str>, message: &str) -> Entry {
+        Entry {
+            timestamp: timestamp.map(str::to_string),
+            level: level.map(str::to_string),
+            message: message.to_string(),
+        }
+    }
+
+    fn write(entries: Vec<Entry>) -> String {
+        let mut out = Vec::new();
+        write_entries(&mut out, entries).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_export() {
+        let input = "timestamp [UTC],Message,severityLevel,itemType\n\
+            \"5/1/2024, 12:34:56.789 PM\",\"started, 3 items\",1,trace\n\
+            \"5/1/2024, 12:34:57.001 PM\",base64-encoded log: d2Fybg==,2,trace\n";
+        let entries = read_csv(input).unwrap();
+        assert_eq!(
+            entries,
+            [
+                entry(
+                    Some("5/1/2024, 12:34:56.789 PM"),
+                    Some("Information"),
+                    "started, 3 items"
+                ),
+                entry(
+                    Some("5/1/2024, 12:34:57.001 PM"),
+                    Some("Warning"),
+                    "base64-encoded log: d2Fybg=="
+                ),
+            ]
+        );
+        assert_eq!(
+            write(entries),
+            "5/1/2024, 12:34:56.789 PM [Information] started, 3 items\n\
+             5/1/2024, 12:34:57.001 PM [Error] warn\n"
+        );
+        assert!(read_csv("timestamp,severityLevel\n").is_err());
+    }
+
+    #[test]
+    fn json_array_and_lines() {
+        let array = r#"[
+            {"timestamp": "2024-05-01T12:34:56Z", "message": "started", "severityLevel": 1},
+            {"Message": "hex-encoded log: 7761726e", "SeverityLevel": "2"},
+            {"severityLevel": 3}
+        ]"#;
+        let expected = [
+            entry(Some("2024-05-01T12:34:56Z"), Some("Information"), "started"),
+            entry(None, Some("Warning"), "hex-encoded log: 7761726e"),
+        ];
+        assert_eq!(read_json(array).unwrap(), expected);
+
+        let lines = r#"{"timestamp": "2024-05-01T12:34:56Z", "message": "started", "severityLevel": 1}
+
+{"Message": "hex-encoded log: 7761726e", "SeverityLevel": "2"}
+"#;
+        assert_eq!(read_json(lines).unwrap(), expected);
+        assert!(read_json("{not json").is_err());
+    }
+
+    #[test]
+    fn as_warning_notice() {
+        let output = write(vec![
+            entry(None, Some("Warning"), decode::AS_WARNING_NOTICE),
+            entry(None, Some("Warning"), "warning: a warning"),
+            entry(None, Some("Warning"), "warning: a warning"),
+        ]);
+        assert_eq!(
+            output,
+            "[Error] warning: a warning\n[Warning] warning: a warning\n"
+        );
+    }
+
+    #[test]
+    fn severity_names() {
+        assert_eq!(severity_name(&Value::from(0)).as_deref(), Some("Verbose"));
+        assert_eq!(
+            severity_name(&Value::from(" 4 ")).as_deref(),
+            Some("Critical")
+        );
+        assert_eq!(severity_name(&Value::from(5)), None);
+        assert_eq!(severity_name(&Value::Null), None);
+    }
+}
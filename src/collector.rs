@@ -0,0 +1,126 @@
+//! Per-invocation log collection.
+//!
+//! A custom handler may return a `Logs` string array in its invocation response. The Azure Function
+//! runtime writes those entries under the function's own log category, grouped with the invocation,
+//! instead of mixing them into the host console. [LogCollector] captures the records logged while it is
+//! entered so that they can be merged into the response payload.
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::thread::LocalKey;
+
+use crate::scope::{self, Scope, ScopeGuard, Scoped};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<LogCollector>> = const { RefCell::new(None) };
+}
+
+/// Collects the log entries of one invocation.
+///
+/// While a collector is entered on a thread, records logged on that thread are pushed into it
+/// instead of being written to stdout/stderr. An async handler, which may move between threads at
+/// every `.await`, should use [LogCollector::scope] instead. The collector can be cloned and shared,
+/// every clone refers to the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct LogCollector(Arc<Mutex<Vec<String>>>);
+
+impl LogCollector {
+    pub fn new() -> LogCollector {
+        LogCollector::default()
+    }
+
+    /// Makes this collector the active one on the current thread until the guard is dropped.
+    /// The previously active collector, if any, is restored afterwards.
+    pub fn enter(&self) -> CollectorGuard {
+        scope::enter(self.clone())
+    }
+
+    /// Makes this collector the active one while `future` is polled, on whichever thread polls it.
+    pub fn scope<F: Future>(self, future: F) -> Scoped<LogCollector, F> {
+        scope::scope(self, future)
+    }
+
+    /// Appends an entry.
+    pub fn push(&self, entry: String) {
+        self.entries().push(entry);
+    }
+
+    /// Takes all the entries collected so far, leaving the collector empty.
+    pub fn take(&self) -> Logs {
+        Logs(std::mem::take(&mut *self.entries()))
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Scope for LogCollector {
+    fn slot() -> &'static LocalKey<RefCell<Option<Self>>> {
+        &ACTIVE
+    }
+}
+
+/// Restores the previously active collector when dropped, see [LogCollector::enter].
+pub type CollectorGuard = ScopeGuard<LogCollector>;
+
+/// The `Logs` array of a custom handler invocation response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Logs(pub Vec<String>);
+
+impl Logs {
+    pub fn into_vec(self) -> Vec<String> {
+        self.0
+    }
+}
+
+/// Pushes the entry built by `entry` into the collector active on the current thread.
+/// Returns false, without calling `entry`, if there is none.
+pub(crate) fn collect_with(entry: impl FnOnce() -> String) -> bool {
+    scope::with_current(|active: Option<&LogCollector>| match active {
+        Some(collector) => {
+            collector.push(entry());
+            true
+        }
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_only_while_entered() {
+        let collector = LogCollector::new();
+        assert!(!collect_with(|| "before".to_string()));
+        {
+            let _guard = collector.enter();
+            assert!(collect_with(|| "during".to_string()));
+        }
+        assert!(!collect_with(|| "after".to_string()));
+        assert_eq!(collector.take().into_vec(), vec!["during".to_string()]);
+        assert!(collector.take().0.is_empty());
+    }
+
+    #[test]
+    fn nested_collectors_restore_previous() {
+        let outer = LogCollector::new();
+        let inner = LogCollector::new();
+        let _outer_guard = outer.enter();
+        {
+            let _inner_guard = inner.enter();
+            assert!(collect_with(|| "inner".to_string()));
+        }
+        assert!(collect_with(|| "outer".to_string()));
+        assert_eq!(inner.take().0, vec!["inner".to_string()]);
+        assert_eq!(outer.take().0, vec!["outer".to_string()]);
+    }
+}
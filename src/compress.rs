@@ -0,0 +1,108 @@
+//! Compressing large error logs before encoding them.
+use std::io::Write;
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::decode::{DEFLATE_BASE64_PREFIX, DEFLATE_HEX_PREFIX};
+use crate::{contains_warn, DefaultTransform, Transform};
+
+/// Deflate-compresses the error logs longer than a threshold before encoding them, reducing the cost of
+/// encoding large logs such as request bodies or stack traces.
+///
+/// The compressed log is base64-encoded with the [DEFLATE_BASE64_PREFIX] prefix, or hex-encoded with the
+/// [DEFLATE_HEX_PREFIX] prefix if base64 contains `warn`. The error logs not longer than the threshold,
+/// and the warning logs, are transformed by [DefaultTransform]. [decode_line](crate::decode::decode_line)
+/// restores all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressTransform {
+    threshold: usize,
+}
+
+impl CompressTransform {
+    /// Compresses the error logs longer than `threshold` bytes.
+    pub fn new(threshold: usize) -> CompressTransform {
+        CompressTransform { threshold }
+    }
+}
+
+impl Default for CompressTransform {
+    /// Compresses the error logs longer than 1 KiB.
+    fn default() -> Self {
+        CompressTransform::new(1024)
+    }
+}
+
+impl Transform for CompressTransform {
+    fn transform_error(&self, msg: String) -> String {
+        if msg.len() <= self.threshold {
+            return DefaultTransform.transform_error(msg);
+        }
+
+        encode(&compress(msg.as_bytes()))
+    }
+
+    fn transform_warning(&self, msg: String) -> String {
+        DefaultTransform.transform_warning(msg)
+    }
+}
+
+// Encodes the compressed log by base64, or hex if base64 contains `warn`.
+fn encode(compressed: &[u8]) -> String {
+    let encoded = base64::encode(compressed);
+    if !contains_warn(&encoded) {
+        DEFLATE_BASE64_PREFIX.to_string() + &encoded
+    } else {
+        DEFLATE_HEX_PREFIX.to_string() + &crate::hex::encode(compressed)
+    }
+}
+
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|()| encoder.finish())
+        .expect("writing to a Vec never fails")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode_line;
+
+    #[test]
+    fn compresses_above_threshold() {
+        let transform = CompressTransform::new(16);
+        let short = transform.transform_error("warn".to_string());
+        assert!(short.starts_with(crate::decode::BASE64_PREFIX));
+
+        let long = "warning: request body {\"id\": 1} ".repeat(100);
+        let transformed = transform.transform_error(long.clone());
+        assert!(transformed.starts_with(DEFLATE_BASE64_PREFIX));
+        assert!(transformed.len() < long.len() / 4);
+        assert!(!contains_warn(&transformed));
+        assert_eq!(decode_line(&transformed), Some(Ok(long)));
+    }
+
+    #[test]
+    fn hex_fallback() {
+        let compressed = compress("warn".repeat(10).as_bytes());
+        assert_eq!(
+            encode(&compressed),
+            DEFLATE_BASE64_PREFIX.to_string() + &base64::encode(&compressed)
+        );
+
+        // Bytes whose base64 is `warnwarn`, which real compressed logs rarely produce.
+        let compressed = base64::decode("warnwarn").unwrap();
+        let line = encode(&compressed);
+        assert_eq!(
+            line,
+            DEFLATE_HEX_PREFIX.to_string() + &crate::hex::encode(&compressed)
+        );
+        assert!(!contains_warn(&line));
+
+        let msg = "warn".repeat(10);
+        let line = DEFLATE_HEX_PREFIX.to_string() + &crate::hex::encode(&compress(msg.as_bytes()));
+        assert_eq!(decode_line(&line), Some(Ok(msg)));
+    }
+}
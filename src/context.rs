@@ -0,0 +1,147 @@
+//! Key-value context attached to the records logged inside a scope.
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::Arc;
+use std::thread::LocalKey;
+
+use crate::scope::{self, Scope, ScopeGuard, Scoped};
+
+thread_local! {
+    static CURRENT: RefCell<Option<LogContext>> = const { RefCell::new(None) };
+}
+
+/// Fields, such as `tenant_id` or `order_id`, rendered into each line logged inside the scope of the
+/// context, such as `{tenant_id=acme order_id=42} order shipped`.
+///
+/// On a thread, the scope is between [LogContext::enter] and the drop of the guard. For async code, which
+/// may move between threads at every `.await`, [LogContext::scope] enters the context each time the future
+/// is polled, with any executor. The fields are part of the message, so they are checked for `warn` and
+/// transformed with it.
+///
+/// ```
+/// use afch_logger::LogContext;
+///
+/// let _guard = LogContext::current().with("tenant_id", "acme").enter();
+/// // To be spawned or awaited.
+/// let _ship = LogContext::current().with("order_id", 42).scope(async {
+///     log::info!("order shipped"); // {tenant_id=acme order_id=42} order shipped
+/// });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogContext {
+    fields: Arc<Vec<(String, String)>>,
+}
+
+impl LogContext {
+    /// An empty context.
+    pub fn new() -> LogContext {
+        LogContext::default()
+    }
+
+    /// The context entered on the current thread, to be extended by [LogContext::with].
+    pub fn current() -> LogContext {
+        scope::with_current(|current: Option<&LogContext>| current.cloned().unwrap_or_default())
+    }
+
+    /// Adds a field, replacing the field with the same key if any.
+    pub fn with(mut self, key: impl Into<String>, value: impl ToString) -> LogContext {
+        let key = key.into();
+        let value = value.to_string();
+        let fields = Arc::make_mut(&mut self.fields);
+        match fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => fields.push((key, value)),
+        }
+        self
+    }
+
+    /// The fields in the order they are added.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Makes this context the current one on the current thread until the guard is dropped. The previous
+    /// context is restored afterwards.
+    pub fn enter(&self) -> LogContextGuard {
+        scope::enter(self.clone())
+    }
+
+    /// Makes this context the current one while `future` is polled.
+    pub fn scope<F: Future>(self, future: F) -> Scoped<LogContext, F> {
+        scope::scope(self, future)
+    }
+
+    // `{key=value ...} `.
+    fn prefix(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut prefix = String::from("{");
+        for (i, (key, value)) in self.fields().enumerate() {
+            if i > 0 {
+                prefix.push(' ');
+            }
+            write_field(&mut prefix, key, value);
+        }
+        prefix.push_str("} ");
+        Some(prefix)
+    }
+}
+
+/// Writes `key=value`, quoting the value if it is empty or has spaces, quotes, braces or line breaks.
+pub(crate) fn write_field(out: &mut String, key: &str, value: &str) {
+    let key = key.replace(char::is_whitespace, "_");
+    let plain = !value.is_empty()
+        && !value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '{' | '}'));
+    let _ = if plain {
+        write!(out, "{}={}", key, value)
+    } else {
+        write!(out, "{}={:?}", key, value)
+    };
+}
+
+impl Scope for LogContext {
+    fn slot() -> &'static LocalKey<RefCell<Option<Self>>> {
+        &CURRENT
+    }
+}
+
+/// Restores the previous context when dropped, see [LogContext::enter].
+pub type LogContextGuard = ScopeGuard<LogContext>;
+
+/// The prefix of the lines by the context of the current thread, if it has any field.
+pub(crate) fn current_prefix() -> Option<String> {
+    scope::with_current(|current: Option<&LogContext>| current.and_then(LogContext::prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_scopes() {
+        assert_eq!(current_prefix(), None);
+        let _outer = LogContext::current().with("tenant_id", "acme").enter();
+        {
+            let _inner = LogContext::current()
+                .with("order_id", 42)
+                .with("note", "two words")
+                .with("tenant_id", "other")
+                .enter();
+            assert_eq!(
+                current_prefix().as_deref(),
+                Some(r#"{tenant_id=other order_id=42 note="two words"} "#)
+            );
+        }
+        assert_eq!(current_prefix().as_deref(), Some("{tenant_id=acme} "));
+        assert_eq!(
+            LogContext::new().with("multi", "a\nb").prefix().as_deref(),
+            Some(r#"{multi="a\nb"} "#)
+        );
+    }
+}
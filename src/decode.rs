@@ -0,0 +1,217 @@
+//! Restoring the error logs transformed by [DefaultTransform](crate::DefaultTransform),
+//! [HexTransform](crate::HexTransform), [EnvelopeTransform](crate::EnvelopeTransform) and, with the
+//! `compress` feature, `CompressTransform`.
+use std::fmt;
+
+use crate::Envelope;
+
+/// Prefix of an error log base64-encoded once.
+pub const BASE64_PREFIX: &str = "base64-encoded log: ";
+/// Prefix of an error log base64-encoded twice.
+pub const BASE64_TWICE_PREFIX: &str = "base64-encoded-twice log: ";
+/// Prefix of a hex-encoded error log.
+pub const HEX_PREFIX: &str = "hex-encoded log: ";
+/// Prefix of a deflate-compressed then base64-encoded error log.
+pub const DEFLATE_BASE64_PREFIX: &str = "deflate-base64-encoded log: ";
+/// Prefix of a deflate-compressed then hex-encoded error log.
+pub const DEFLATE_HEX_PREFIX: &str = "deflate-hex-encoded log: ";
+/// Prefix of a base64-encoded [Envelope].
+pub const ENVELOPE_BASE64_PREFIX: &str = "envelope-base64 log: ";
+/// Prefix of a hex-encoded [Envelope].
+pub const ENVELOPE_HEX_PREFIX: &str = "envelope-hex log: ";
+/// The line preceding an error log that has to be logged as a warning.
+pub const AS_WARNING_NOTICE: &str = "The following error log has to be logged as Warning: ";
+
+type Decoder = fn(&str) -> Result<Vec<u8>, DecodeError>;
+
+const DECODERS: &[(&str, Decoder)] = &[
+    (BASE64_PREFIX, decode_base64),
+    (BASE64_TWICE_PREFIX, |encoded| {
+        let once = decode_base64(encoded)?;
+        decode_base64(std::str::from_utf8(&once).map_err(|_| DecodeError::InvalidBase64)?)
+    }),
+    (HEX_PREFIX, decode_hex),
+    (ENVELOPE_BASE64_PREFIX, |encoded| {
+        Ok(parse_envelope(decode_base64(encoded)?)?
+            .message
+            .into_bytes())
+    }),
+    (ENVELOPE_HEX_PREFIX, |encoded| {
+        Ok(parse_envelope(decode_hex(encoded)?)?.message.into_bytes())
+    }),
+    #[cfg(feature = "compress")]
+    (DEFLATE_BASE64_PREFIX, |encoded| {
+        inflate(&decode_base64(encoded)?)
+    }),
+    #[cfg(feature = "compress")]
+    (DEFLATE_HEX_PREFIX, |encoded| inflate(&decode_hex(encoded)?)),
+];
+
+const ENVELOPE_DECODERS: &[(&str, Decoder)] = &[
+    (ENVELOPE_BASE64_PREFIX, decode_base64),
+    (ENVELOPE_HEX_PREFIX, decode_hex),
+];
+
+/// Decodes a line written by one of the transforms of this crate for an error log.
+///
+/// The prefix of the encoded log may follow other text, such as the timestamp and level of a console
+/// capture, which is kept in front of the decoded message. Returns `None` if the line is not encoded,
+/// otherwise the original message, or an error if the encoded part is invalid, for example because the
+/// line has been truncated.
+pub fn decode_line(line: &str) -> Option<Result<String, DecodeError>> {
+    let (before, decode, encoded) = find_prefix(line, DECODERS)?;
+    Some(
+        decode(encoded.trim())
+            .and_then(|bytes| String::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8))
+            .map(|msg| before.to_string() + &msg),
+    )
+}
+
+/// Decodes a line written by [EnvelopeTransform](crate::EnvelopeTransform), restoring the metadata of
+/// the record as well as the message.
+///
+/// Returns `None` if the line has no envelope, otherwise the envelope, or an error if it is invalid or
+/// has been truncated. As for [decode_line], the prefix may follow other text, which is not part of the
+/// envelope.
+pub fn decode_envelope(line: &str) -> Option<Result<Envelope, DecodeError>> {
+    let (_, decode, encoded) = find_prefix(line, ENVELOPE_DECODERS)?;
+    Some(decode(encoded.trim()).and_then(parse_envelope))
+}
+
+// Finds the first of `decoders` whose prefix is in `line`, returning the text before the prefix, the
+// decoder and the text after. `base64-encoded log: ` is in `deflate-base64-encoded log: `, so the earliest
+// prefix is taken.
+fn find_prefix<'a>(
+    line: &'a str,
+    decoders: &[(&str, Decoder)],
+) -> Option<(&'a str, Decoder, &'a str)> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    decoders
+        .iter()
+        .filter_map(|&(prefix, decode)| Some((line.find(prefix)?, prefix, decode)))
+        .min_by_key(|&(start, prefix, _)| (start, std::cmp::Reverse(prefix.len())))
+        .map(|(start, prefix, decode)| (&line[..start], decode, &line[start + prefix.len()..]))
+}
+
+fn parse_envelope(bytes: Vec<u8>) -> Result<Envelope, DecodeError> {
+    Envelope::from_text(&String::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)?)
+}
+
+/// Returns true if the line ends with [AS_WARNING_NOTICE], meaning the next line is an error log.
+pub fn is_as_warning_notice(line: &str) -> bool {
+    line.trim_end().ends_with(AS_WARNING_NOTICE.trim_end())
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, DecodeError> {
+    base64::decode(encoded).map_err(|_| DecodeError::InvalidBase64)
+}
+
+fn decode_hex(encoded: &str) -> Result<Vec<u8>, DecodeError> {
+    crate::hex::decode(encoded).ok_or(DecodeError::InvalidHex)
+}
+
+#[cfg(feature = "compress")]
+fn inflate(compressed: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    use std::io::Read;
+
+    let mut bytes = Vec::new();
+    flate2::read::DeflateDecoder::new(compressed)
+        .read_to_end(&mut bytes)
+        .map_err(|_| DecodeError::InvalidCompressed)?;
+    Ok(bytes)
+}
+
+/// Error of [decode_line] and [decode_envelope].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidBase64,
+    InvalidHex,
+    InvalidCompressed,
+    InvalidUtf8,
+    InvalidEnvelope,
+    /// The checksum of an envelope does not match, usually because the line has been truncated.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DecodeError::InvalidBase64 => "invalid or truncated base64",
+            DecodeError::InvalidHex => "invalid or truncated hex",
+            DecodeError::InvalidCompressed => "invalid or truncated compressed data",
+            DecodeError::InvalidUtf8 => "decoded log is not UTF-8",
+            DecodeError::InvalidEnvelope => "invalid envelope",
+            DecodeError::ChecksumMismatch => "checksum mismatch, the log may have been truncated",
+        })
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DefaultTransform, RecordTransform, Transform};
+
+    #[test]
+    fn round_trip() {
+        for msg in ["a warning", "multi\nline warning", "WWARN"] {
+            let line = DefaultTransform.transform_error(msg.to_string());
+            assert_eq!(decode_line(&line), Some(Ok(msg.to_string())));
+        }
+    }
+
+    #[test]
+    fn twice() {
+        let line = BASE64_TWICE_PREFIX.to_string() + &base64::encode(base64::encode("warn"));
+        assert_eq!(decode_line(&line), Some(Ok("warn".to_string())));
+    }
+
+    #[test]
+    fn not_encoded_or_invalid() {
+        assert_eq!(decode_line("plain error"), None);
+        assert_eq!(
+            decode_line("base64-encoded log: d2Fy*"),
+            Some(Err(DecodeError::InvalidBase64))
+        );
+        assert_eq!(
+            decode_line("hex-encoded log: 77617"),
+            Some(Err(DecodeError::InvalidHex))
+        );
+        assert!(is_as_warning_notice(AS_WARNING_NOTICE.trim_end()));
+    }
+
+    #[test]
+    fn after_leading_text() {
+        assert_eq!(
+            decode_line("2024-05-01T12:34:56Z [Error] base64-encoded log: d2Fybg=="),
+            Some(Ok("2024-05-01T12:34:56Z [Error] warn".to_string()))
+        );
+        assert_eq!(
+            decode_line("[Error] hex-encoded log: 7761726e\r\n"),
+            Some(Ok("[Error] warn".to_string()))
+        );
+        let record = log::Record::builder().build();
+        let line = crate::EnvelopeTransform.rewrite_error(&record, "warn");
+        assert_eq!(
+            decode_envelope(&format!("[Error] {}", line)),
+            decode_envelope(&line)
+        );
+        assert!(is_as_warning_notice(&format!(
+            "2024-05-01T12:34:56Z [Error] {}",
+            AS_WARNING_NOTICE
+        )));
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn earliest_prefix() {
+        let msg = "warn".repeat(10);
+        let line = crate::CompressTransform::new(0).transform_error(msg.clone());
+        assert!(line.starts_with(DEFLATE_BASE64_PREFIX));
+        assert_eq!(
+            decode_line(&format!("[Error] {}", line)),
+            Some(Ok(format!("[Error] {}", msg)))
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Encoding error logs together with the metadata of their records.
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::decode::{DecodeError, ENVELOPE_BASE64_PREFIX, ENVELOPE_HEX_PREFIX};
+use crate::{contains_warn, DefaultTransform, RecordTransform};
+
+const HEADER: &str = "afch-envelope 1";
+
+/// An error log with the metadata of its record, as encoded by [EnvelopeTransform].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub level: log::Level,
+    pub target: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Milliseconds since the Unix epoch when the log was encoded.
+    pub timestamp_millis: u64,
+    pub message: String,
+}
+
+impl Envelope {
+    /// Creates the envelope of `message` logged by `record`, timestamped now.
+    pub fn new(record: &log::Record, message: String) -> Envelope {
+        Envelope {
+            level: record.level(),
+            target: record.target().to_string(),
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+            timestamp_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            message,
+        }
+    }
+
+    /// Encodes the envelope into a line that does not contain `warn`, base64-encoded with the
+    /// [ENVELOPE_BASE64_PREFIX] prefix, or hex-encoded with the [ENVELOPE_HEX_PREFIX] prefix if base64
+    /// contains `warn`.
+    pub fn encode(&self) -> String {
+        let text = self.to_text();
+        let encoded = base64::encode(&text);
+        if !contains_warn(&encoded) {
+            ENVELOPE_BASE64_PREFIX.to_string() + &encoded
+        } else {
+            ENVELOPE_HEX_PREFIX.to_string() + &crate::hex::encode(text.as_bytes())
+        }
+    }
+
+    // The header lines, then an empty line and the message. The checksum covers the message, which comes
+    // last, so that a truncated envelope is detected.
+    fn to_text(&self) -> String {
+        let mut text = String::with_capacity(self.message.len() + 128);
+        let _ = writeln!(text, "{}", HEADER);
+        let _ = writeln!(text, "level: {}", self.level);
+        let _ = writeln!(text, "target: {}", single_line(&self.target));
+        if let Some(module_path) = &self.module_path {
+            let _ = writeln!(text, "module: {}", single_line(module_path));
+        }
+        if let Some(file) = &self.file {
+            let _ = writeln!(text, "file: {}", single_line(file));
+        }
+        if let Some(line) = self.line {
+            let _ = writeln!(text, "line: {}", line);
+        }
+        let _ = writeln!(text, "timestamp: {}", self.timestamp_millis);
+        let _ = writeln!(text, "crc32: {:08x}", crc32(self.message.as_bytes()));
+        text.push('\n');
+        text.push_str(&self.message);
+        text
+    }
+
+    pub(crate) fn from_text(text: &str) -> Result<Envelope, DecodeError> {
+        let (header, message) = text
+            .split_once("\n\n")
+            .ok_or(DecodeError::InvalidEnvelope)?;
+        let mut lines = header.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(DecodeError::InvalidEnvelope);
+        }
+
+        let mut envelope = Envelope {
+            level: log::Level::Error,
+            target: String::new(),
+            module_path: None,
+            file: None,
+            line: None,
+            timestamp_millis: 0,
+            message: message.to_string(),
+        };
+        let mut checksum = None;
+        for line in lines {
+            let (key, value) = line.split_once(": ").ok_or(DecodeError::InvalidEnvelope)?;
+            match key {
+                "level" => envelope.level = value.parse().map_err(invalid)?,
+                "target" => envelope.target = value.to_string(),
+                "module" => envelope.module_path = Some(value.to_string()),
+                "file" => envelope.file = Some(value.to_string()),
+                "line" => envelope.line = Some(value.parse().map_err(invalid)?),
+                "timestamp" => envelope.timestamp_millis = value.parse().map_err(invalid)?,
+                "crc32" => checksum = Some(u32::from_str_radix(value, 16).map_err(invalid)?),
+                // Unknown keys are left for future versions.
+                _ => {}
+            }
+        }
+        if checksum != Some(crc32(envelope.message.as_bytes())) {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+        Ok(envelope)
+    }
+}
+
+fn invalid<E>(_: E) -> DecodeError {
+    DecodeError::InvalidEnvelope
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], " ")
+}
+
+/// CRC-32 (IEEE).
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Encodes the error logs containing `warn` into an [Envelope], keeping the level, target, module path,
+/// location and time of the record, with a checksum to detect truncated logs.
+/// [decode_envelope](crate::decode::decode_envelope) restores the envelope. Warning logs are transformed
+/// the same way as [DefaultTransform] does.
+pub struct EnvelopeTransform;
+
+impl RecordTransform for EnvelopeTransform {
+    fn rewrite_error<'a>(&self, record: &log::Record, msg: &'a str) -> Cow<'a, str> {
+        Cow::Owned(Envelope::new(record, msg.to_string()).encode())
+    }
+
+    fn rewrite_warning<'a>(&self, record: &log::Record, line: &'a str) -> Cow<'a, str> {
+        DefaultTransform.rewrite_warning(record, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode_envelope, decode_line};
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = log::Record::builder()
+            .level(log::Level::Error)
+            .target("my_func::db")
+            .module_path(Some("my_func::db"))
+            .file(Some("src/db.rs"))
+            .line(Some(42))
+            .build();
+        let line = EnvelopeTransform.rewrite_error(&record, "warning\nmulti-line");
+        assert!(!contains_warn(&line));
+
+        let envelope = decode_envelope(&line).unwrap().unwrap();
+        assert_eq!(envelope.level, log::Level::Error);
+        assert_eq!(envelope.target, "my_func::db");
+        assert_eq!(envelope.module_path.as_deref(), Some("my_func::db"));
+        assert_eq!(envelope.file.as_deref(), Some("src/db.rs"));
+        assert_eq!(envelope.line, Some(42));
+        assert!(envelope.timestamp_millis > 0);
+        assert_eq!(envelope.message, "warning\nmulti-line");
+        assert_eq!(
+            decode_line(&line),
+            Some(Ok("warning\nmulti-line".to_string()))
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let record = log::Record::builder().level(log::Level::Error).build();
+        let line = EnvelopeTransform.rewrite_error(&record, "a warning that gets truncated");
+        let truncated = &line[..line.len() - 8];
+        assert_eq!(
+            decode_envelope(truncated),
+            Some(Err(DecodeError::ChecksumMismatch))
+        );
+    }
+
+    #[test]
+    fn hex() {
+        let envelope = Envelope::new(&log::Record::builder().build(), "warn".to_string());
+        let line =
+            ENVELOPE_HEX_PREFIX.to_string() + &crate::hex::encode(envelope.to_text().as_bytes());
+        assert_eq!(decode_envelope(&line), Some(Ok(envelope)));
+    }
+}
@@ -0,0 +1,104 @@
+//! Readable escaping of the `warn` occurrences in error logs.
+//!
+//! [escape] rewrites the `a` of each `warn` (case insensitive) as its percent-encoding, `warn` becoming
+//! `w%61rn` and `WARN` becoming `W%41RN`. To keep it reversible, a `%` followed by two hex digits is
+//! escaped as `%25`. Everything else is left as is, and [unescape] restores the original message.
+use crate::{contains_warn, DefaultTransform, Transform};
+
+/// Escapes the `warn` occurrences of the error logs by [escape], keeping them readable. Warning logs get
+/// the prefix of [DefaultTransform].
+///
+/// Unlike encoding, the escaped log is not marked, so use [unescape] only on the logs known to be
+/// escaped.
+pub struct EscapeTransform;
+
+impl Transform for EscapeTransform {
+    fn transform_error(&self, msg: String) -> String {
+        escape(&msg)
+    }
+
+    fn transform_warning(&self, msg: String) -> String {
+        DefaultTransform.transform_warning(msg)
+    }
+}
+
+/// Escapes `msg` so that it does not contain `warn`, see the [module](self) documentation.
+pub fn escape(msg: &str) -> String {
+    let bytes = msg.as_bytes();
+    let mut escaped = String::with_capacity(msg.len() + 8);
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &msg[i..];
+        if let Some(warn) = rest.get(..4).filter(|head| contains_warn(head)) {
+            escaped.push_str(&warn[..1]);
+            escaped.push_str(if bytes[i + 1] == b'a' { "%61" } else { "%41" });
+            escaped.push_str(&warn[2..]);
+            i += 4;
+        } else if is_percent_escape(rest) {
+            escaped.push_str("%25");
+            i += 1;
+        } else {
+            let ch = rest.chars().next().unwrap();
+            escaped.push(ch);
+            i += ch.len_utf8();
+        }
+    }
+    escaped
+}
+
+/// Restores the message escaped by [escape].
+pub fn unescape(escaped: &str) -> String {
+    let mut msg = String::with_capacity(escaped.len());
+    let mut rest = escaped;
+    while let Some(percent) = rest.find('%') {
+        msg.push_str(&rest[..percent]);
+        rest = &rest[percent..];
+        match u8::from_str_radix(rest.get(1..3).unwrap_or(""), 16) {
+            Ok(byte) if is_percent_escape(rest) && byte.is_ascii() => {
+                msg.push(byte as char);
+                rest = &rest[3..];
+            }
+            _ => {
+                msg.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+    msg.push_str(rest);
+    msg
+}
+
+fn is_percent_escape(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.len() >= 3
+        && bytes[0] == b'%'
+        && bytes[1].is_ascii_hexdigit()
+        && bytes[2].is_ascii_hexdigit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_only_warn() {
+        assert_eq!(
+            escape("warning_threshold exceeded, WaRn"),
+            "w%61rning_threshold exceeded, W%61Rn"
+        );
+        assert_eq!(escape("wwarn 100% %41"), "ww%61rn 100% %2541");
+        assert_eq!(escape("WARN"), "W%41RN");
+        assert_eq!(escape("no match, 界"), "no match, 界");
+    }
+
+    #[test]
+    fn round_trip() {
+        const ALPHABET: [char; 10] = ['w', 'a', 'A', 'r', 'n', 'N', '%', '4', '1', '界'];
+
+        for msg in crate::tests::random_strings(0x9e37_79b9_7f4a_7c15, &ALPHABET, 24).take(20_000) {
+            let escaped = escape(&msg);
+            assert!(!contains_warn(&escaped), "{:?}", msg);
+            assert_eq!(unescape(&escaped), msg);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! `RUST_LOG` style directives setting the level per target.
+use std::fmt;
+use std::str::FromStr;
+
+use log::{LevelFilter, Metadata};
+
+/// A list of comma separated directives, such as `info,hyper=warn,my_func::db=debug`.
+///
+/// A directive is either `target=level`, which applies to the target and the targets under it (for module
+/// paths, the `my_func::db` directive applies to `my_func::db::pool` but not to `my_func::dbx`), or
+/// a bare `level` for the targets not matched by any directive. The longest matching directive is used,
+/// and among the same targets the last one. Without a bare level, the targets not matched log at Info.
+/// A bare target enables all levels for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directives {
+    default: LevelFilter,
+    // Sorted by the length of the target, longest first.
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Directives {
+    pub fn parse(spec: &str) -> Result<Directives, ParseDirectivesError> {
+        let mut default = LevelFilter::Info;
+        let mut targets = Vec::new();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let invalid = || ParseDirectivesError {
+                directive: directive.to_string(),
+            };
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(invalid());
+                    }
+                    let level = level.trim().parse().map_err(|_| invalid())?;
+                    targets.push((target.to_string(), level));
+                }
+                None => match directive.parse() {
+                    Ok(level) => default = level,
+                    Err(_) => targets.push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        // The sort is stable, so after reversing, the last directive of the same target is found first.
+        targets.reverse();
+        targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        Ok(Directives { default, targets })
+    }
+
+    /// Replaces the level of the targets not matched by any directive.
+    pub fn with_default(mut self, level: LevelFilter) -> Directives {
+        self.default = level;
+        self
+    }
+
+    /// Reads the directives from the environment variable `name`, `None` if it is not set.
+    pub fn from_env(name: &str) -> Result<Option<Directives>, ParseDirectivesError> {
+        match std::env::var(name) {
+            Ok(spec) => Directives::parse(&spec).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The level applied to `target`.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| matches_target(prefix, target))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    /// The most verbose level of all the directives.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Default for Directives {
+    /// Logs Info and above for all targets.
+    fn default() -> Self {
+        Directives {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Directives {
+    type Err = ParseDirectivesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Directives::parse(s)
+    }
+}
+
+fn matches_target(prefix: &str, target: &str) -> bool {
+    match target.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Error of parsing [Directives].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDirectivesError {
+    directive: String,
+}
+
+impl fmt::Display for ParseDirectivesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log directive `{}`", self.directive)
+    }
+}
+
+impl std::error::Error for ParseDirectivesError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_match() {
+        let directives = Directives::parse("hyper=warn, my_func::db=debug,my_func=error").unwrap();
+        assert_eq!(directives.level_for("hyper"), LevelFilter::Warn);
+        assert_eq!(directives.level_for("hyper::client"), LevelFilter::Warn);
+        assert_eq!(directives.level_for("hyperx"), LevelFilter::Info);
+        assert_eq!(directives.level_for("my_func"), LevelFilter::Error);
+        assert_eq!(
+            directives.level_for("my_func::db::pool"),
+            LevelFilter::Debug
+        );
+        assert_eq!(directives.level_for("my_func::http"), LevelFilter::Error);
+        assert_eq!(directives.level_for("other"), LevelFilter::Info);
+        assert_eq!(directives.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn default_and_bare_target() {
+        let directives: Directives = "off,my_func,hyper=info,hyper=trace".parse().unwrap();
+        assert_eq!(directives.level_for("other"), LevelFilter::Off);
+        assert_eq!(directives.level_for("my_func::db"), LevelFilter::Trace);
+        assert_eq!(directives.level_for("hyper"), LevelFilter::Trace);
+        assert!(Directives::parse("").unwrap().enabled(
+            &Metadata::builder()
+                .level(log::Level::Info)
+                .target("any")
+                .build()
+        ));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Directives::parse("hyper=loud").unwrap_err().to_string(),
+            "invalid log directive `hyper=loud`"
+        );
+        assert!(Directives::parse("=warn").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Hex encoding of error logs, which can never contain `warn`.
+use crate::decode::HEX_PREFIX;
+use crate::{DefaultTransform, Transform};
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Hex-encodes the error logs containing `warn`.
+///
+/// Unlike [DefaultTransform], the encoded log can never contain `warn`, as there is
+/// no `w` among the hex digits nor in the prefix, so an error log is never downgraded to a warning. The
+/// cost is that the encoded log is twice as long as the original. Warning logs are left to [DefaultTransform].
+pub struct HexTransform;
+
+impl Transform for HexTransform {
+    fn transform_error(&self, msg: String) -> String {
+        let mut transformed = String::with_capacity(HEX_PREFIX.len() + msg.len() * 2);
+        transformed.push_str(HEX_PREFIX);
+        transformed.push_str(&encode(msg.as_bytes()));
+        transformed
+    }
+
+    fn transform_warning(&self, msg: String) -> String {
+        DefaultTransform.transform_warning(msg)
+    }
+}
+
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| [DIGITS[(b >> 4) as usize], DIGITS[(b & 0xf) as usize]])
+        .map(char::from)
+        .collect()
+}
+
+pub(crate) fn decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high * 16 + low) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contains_warn;
+
+    // The encoded log is the prefix followed by digits. `warn` can only occur within the prefix, within
+    // the digits, or across them, and each of them requires a `w` in the prefix or the digits.
+    #[test]
+    fn no_w_in_alphabet_or_prefix() {
+        assert!(!DIGITS.iter().any(|d| d.eq_ignore_ascii_case(&b'w')));
+        assert!(!HEX_PREFIX.chars().any(|c| c.eq_ignore_ascii_case(&'w')));
+    }
+
+    #[test]
+    fn exhaustive_two_bytes() {
+        for high in 0..=u8::MAX {
+            for low in 0..=u8::MAX {
+                let encoded = encode(&[high, low]);
+                assert!(!contains_warn(&encoded));
+                assert_eq!(decode(&encoded), Some(vec![high, low]));
+            }
+        }
+    }
+
+    #[test]
+    fn transform_error() {
+        let transformed = HexTransform.transform_error("WARN: disk".to_string());
+        assert!(!contains_warn(&transformed));
+        assert_eq!(transformed, "hex-encoded log: 5741524e3a206469736b");
+        assert_eq!(
+            crate::decode::decode_line(&transformed),
+            Some(Ok("WARN: disk".to_string()))
+        );
+    }
+}
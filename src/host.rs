@@ -0,0 +1,202 @@
+//! Log levels configured for the Azure Function host.
+//!
+//! The host reads `logging.logLevel` from `host.json`, which can be overridden by the app settings
+//! `AzureFunctionsJobHost__logging__logLevel__<category>`. [HostLogLevels] reads the same settings so that
+//! they also control the logs of the handler.
+use log::LevelFilter;
+
+use crate::Directives;
+
+const ENV_PREFIX: &str = "AzureFunctionsJobHost__logging__logLevel__";
+
+/// The category whose level applies to the handler by default, the one of the function logs.
+pub const FUNCTION_CATEGORY: &str = "Function";
+
+/// The log level of each category, following the rules of the host: the level of a category is the one
+/// of the longest configured category equal to it or being its dot separated prefix, otherwise the one of
+/// `default`, otherwise Information.
+///
+/// The categories are case insensitive. The level names are the .NET ones, `Trace`, `Debug`,
+/// `Information`, `Warning`, `Error`, `Critical` and `None`, the settings with other values are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostLogLevels {
+    // Lowercase category and its level.
+    levels: Vec<(String, LevelFilter)>,
+}
+
+impl HostLogLevels {
+    /// Reads the `AzureFunctionsJobHost__logging__logLevel__*` environment variables.
+    pub fn from_env() -> HostLogLevels {
+        HostLogLevels::from_vars(std::env::vars())
+    }
+
+    /// Reads the `AzureFunctionsJobHost__logging__logLevel__*` settings among `vars`.
+    pub fn from_vars<K: AsRef<str>, V: AsRef<str>>(
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> HostLogLevels {
+        let mut levels = HostLogLevels::default();
+        for (name, value) in vars {
+            let name = name.as_ref();
+            let category = match name.get(..ENV_PREFIX.len()) {
+                Some(prefix) if prefix.eq_ignore_ascii_case(ENV_PREFIX) => {
+                    &name[ENV_PREFIX.len()..]
+                }
+                _ => continue,
+            };
+            levels.set(category, value.as_ref());
+        }
+        levels
+    }
+
+    /// Reads `logging.logLevel` of the content of `host.json`.
+    #[cfg(feature = "host-json")]
+    pub fn from_host_json(json: &str) -> Result<HostLogLevels, serde_json::Error> {
+        let host: serde_json::Value = serde_json::from_str(json)?;
+        let mut levels = HostLogLevels::default();
+        let log_level =
+            get_ignore_case(&host, "logging").and_then(|l| get_ignore_case(l, "logLevel"));
+        if let Some(serde_json::Value::Object(log_level)) = log_level {
+            for (category, level) in log_level {
+                if let Some(level) = level.as_str() {
+                    levels.set(category, level);
+                }
+            }
+        }
+        Ok(levels)
+    }
+
+    /// Reads the `host.json` at `path`, then the environment variables, which take precedence like they do
+    /// for the host.
+    #[cfg(feature = "host-json")]
+    pub fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<HostLogLevels> {
+        let json = std::fs::read_to_string(path)?;
+        let mut levels = HostLogLevels::from_host_json(&json)?;
+        levels.merge(HostLogLevels::from_env());
+        Ok(levels)
+    }
+
+    /// Overrides the levels by the ones of `other`.
+    pub fn merge(&mut self, other: HostLogLevels) {
+        for (category, level) in other.levels {
+            self.insert(category, level);
+        }
+    }
+
+    /// The level of `category`.
+    pub fn level_for(&self, category: &str) -> LevelFilter {
+        let category = category.to_ascii_lowercase();
+        self.levels
+            .iter()
+            .filter(|(configured, _)| {
+                category
+                    .strip_prefix(configured.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            .max_by_key(|(configured, _)| configured.len())
+            .or_else(|| {
+                self.levels
+                    .iter()
+                    .find(|(configured, _)| configured == "default")
+            })
+            .map_or(LevelFilter::Info, |(_, level)| *level)
+    }
+
+    /// The directives logging all targets at the level of [FUNCTION_CATEGORY].
+    pub fn directives(&self) -> Directives {
+        Directives::default().with_default(self.level_for(FUNCTION_CATEGORY))
+    }
+
+    fn set(&mut self, category: &str, level: &str) {
+        if let Some(level) = parse_level(level) {
+            self.insert(category.to_ascii_lowercase(), level);
+        }
+    }
+
+    fn insert(&mut self, category: String, level: LevelFilter) {
+        match self.levels.iter_mut().find(|(c, _)| *c == category) {
+            Some(existing) => existing.1 = level,
+            None => self.levels.push((category, level)),
+        }
+    }
+}
+
+fn parse_level(level: &str) -> Option<LevelFilter> {
+    Some(match level.trim().to_ascii_lowercase().as_str() {
+        "trace" => LevelFilter::Trace,
+        "debug" => LevelFilter::Debug,
+        "information" => LevelFilter::Info,
+        "warning" => LevelFilter::Warn,
+        "error" | "critical" => LevelFilter::Error,
+        "none" => LevelFilter::Off,
+        _ => return None,
+    })
+}
+
+#[cfg(any(feature = "host-json", feature = "invocation-json"))]
+pub(crate) fn get_ignore_case<'a>(
+    value: &'a serde_json::Value,
+    key: &str,
+) -> Option<&'a serde_json::Value> {
+    value
+        .as_object()?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_vars() {
+        let levels = HostLogLevels::from_vars([
+            (
+                "AzureFunctionsJobHost__logging__logLevel__default",
+                "Warning",
+            ),
+            (
+                "AZUREFUNCTIONSJOBHOST__LOGGING__LOGLEVEL__Function",
+                "Debug",
+            ),
+            (
+                "AzureFunctionsJobHost__logging__logLevel__Function.Noisy",
+                "None",
+            ),
+            (
+                "AzureFunctionsJobHost__logging__logLevel__Host.Results",
+                "loud",
+            ),
+            ("PATH", "/usr/bin"),
+        ]);
+        assert_eq!(levels.level_for("Host.Results"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("function"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("Function.Other.User"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("Function.Noisy.User"), LevelFilter::Off);
+        assert_eq!(levels.level_for("Function.NoisyOther"), LevelFilter::Debug);
+        assert_eq!(levels.directives().level_for("any"), LevelFilter::Debug);
+        assert_eq!(
+            HostLogLevels::default().level_for("Function"),
+            LevelFilter::Info
+        );
+    }
+
+    #[cfg(feature = "host-json")]
+    #[test]
+    fn from_host_json() {
+        let mut levels = HostLogLevels::from_host_json(
+            r#"{
+                "version": "2.0",
+                "logging": { "logLevel": { "default": "Error", "Function": "Critical" } }
+            }"#,
+        )
+        .unwrap();
+        levels.merge(HostLogLevels::from_vars([(
+            "AzureFunctionsJobHost__logging__logLevel__function",
+            "Trace",
+        )]));
+        assert_eq!(levels.level_for("Host"), LevelFilter::Error);
+        assert_eq!(levels.level_for("Function"), LevelFilter::Trace);
+        assert!(HostLogLevels::from_host_json("{").is_err());
+    }
+}
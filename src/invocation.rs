@@ -0,0 +1,181 @@
+//! The context of a custom handler invocation.
+use std::cell::RefCell;
+use std::future::Future;
+use std::thread::LocalKey;
+
+use crate::scope::{self, Scope, ScopeGuard, Scoped};
+
+thread_local! {
+    static ACTIVE: RefCell<Option<InvocationContext>> = const { RefCell::new(None) };
+}
+
+/// The header of the requests from the host to the custom handler carrying the invocation ID.
+pub const INVOCATION_ID_HEADER: &str = "X-Azure-Functions-InvocationId";
+
+/// The context of an invocation, read from the request of the host by `InvocationContext::from_request`
+/// with the `invocation-json` feature, or filled in by hand.
+///
+/// While the context is [entered](InvocationContext::enter), each line logged on the thread is prefixed by
+/// the function name and the invocation ID, such as `[HttpTrigger 2e7a1c5b-...] `. Custom handlers are
+/// usually async, so scope the handling future by [InvocationContext::scope] instead, which keeps the prefix
+/// across the `.await` points on any executor.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvocationContext {
+    /// `Metadata.InvocationId` of the request. The host also sends it in the [INVOCATION_ID_HEADER] header.
+    pub invocation_id: Option<String>,
+    /// `Metadata.sys.MethodName` of the request.
+    pub function_name: Option<String>,
+    /// `Metadata.sys.UtcNow` of the request, the time the host triggered the invocation.
+    pub utc_now: Option<String>,
+    /// The W3C `traceparent` header of the HTTP request that triggered the invocation.
+    pub traceparent: Option<String>,
+}
+
+impl InvocationContext {
+    /// Reads the context from the JSON payload of a custom handler request. The fields not found are
+    /// left empty.
+    #[cfg(feature = "invocation-json")]
+    pub fn from_request(json: &str) -> Result<InvocationContext, serde_json::Error> {
+        let request: serde_json::Value = serde_json::from_str(json)?;
+        Ok(InvocationContext::from_request_value(&request))
+    }
+
+    /// Same as [InvocationContext::from_request], for the payload already parsed.
+    #[cfg(feature = "invocation-json")]
+    pub fn from_request_value(request: &serde_json::Value) -> InvocationContext {
+        use crate::host::get_ignore_case;
+
+        let string = |value: Option<&serde_json::Value>| value?.as_str().map(str::to_string);
+        let metadata = get_ignore_case(request, "Metadata");
+        let sys = metadata.and_then(|m| get_ignore_case(m, "sys"));
+        // The headers are in the metadata of HTTP triggers, and in the `req` data of the HTTP requests
+        // forwarded as they are. Each header has an array of values.
+        let traceparent = metadata
+            .into_iter()
+            .chain(
+                get_ignore_case(request, "Data")
+                    .and_then(serde_json::Value::as_object)
+                    .into_iter()
+                    .flat_map(|data| data.values()),
+            )
+            .filter_map(|value| get_ignore_case(value, "Headers"))
+            .filter_map(|headers| get_ignore_case(headers, "traceparent"))
+            .find_map(|value| match value {
+                serde_json::Value::Array(values) => string(values.first()),
+                value => string(Some(value)),
+            });
+
+        InvocationContext {
+            invocation_id: string(metadata.and_then(|m| get_ignore_case(m, "InvocationId"))),
+            function_name: string(sys.and_then(|s| get_ignore_case(s, "MethodName"))),
+            utc_now: string(sys.and_then(|s| get_ignore_case(s, "UtcNow"))),
+            traceparent,
+        }
+    }
+
+    /// Makes the logger prefix the lines logged on the current thread by this context until the guard is
+    /// dropped. The previously entered context, if any, is restored afterwards.
+    pub fn enter(&self) -> InvocationGuard {
+        scope::enter(self.clone())
+    }
+
+    /// Makes the logger prefix the lines logged while `future` is polled by this context.
+    pub fn scope<F: Future>(self, future: F) -> Scoped<InvocationContext, F> {
+        scope::scope(self, future)
+    }
+
+    fn prefix(&self) -> Option<String> {
+        match (&self.function_name, &self.invocation_id) {
+            (Some(name), Some(id)) => Some(format!("[{} {}] ", name, id)),
+            (Some(one), None) | (None, Some(one)) => Some(format!("[{}] ", one)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Scope for InvocationContext {
+    fn slot() -> &'static LocalKey<RefCell<Option<Self>>> {
+        &ACTIVE
+    }
+}
+
+/// Restores the previously entered context when dropped, see [InvocationContext::enter].
+pub type InvocationGuard = ScopeGuard<InvocationContext>;
+
+/// The prefix of the lines by the context entered on the current thread, if any.
+pub(crate) fn active_prefix() -> Option<String> {
+    scope::with_current(|active: Option<&InvocationContext>| {
+        active.and_then(InvocationContext::prefix)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enter() {
+        let context = InvocationContext {
+            invocation_id: Some("42".to_string()),
+            function_name: Some("HttpTrigger".to_string()),
+            ..InvocationContext::default()
+        };
+        assert_eq!(active_prefix(), None);
+        {
+            let _guard = context.enter();
+            assert_eq!(active_prefix().as_deref(), Some("[HttpTrigger 42] "));
+            {
+                let _inner = InvocationContext {
+                    invocation_id: Some("43".to_string()),
+                    ..InvocationContext::default()
+                }
+                .enter();
+                assert_eq!(active_prefix().as_deref(), Some("[43] "));
+            }
+            assert_eq!(active_prefix().as_deref(), Some("[HttpTrigger 42] "));
+        }
+        assert_eq!(active_prefix(), None);
+    }
+
+    #[cfg(feature = "invocation-json")]
+    #[test]
+    fn from_request() {
+        let context = InvocationContext::from_request(
+            r#"{
+                "Data": {
+                    "req": {
+                        "Url": "http://localhost:7071/api/orders",
+                        "Method": "POST",
+                        "Headers": { "Traceparent": ["00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"] }
+                    }
+                },
+                "Metadata": {
+                    "InvocationId": "2e7a1c5b-7d3e-4a5f-9d1c-3b2a1f0e9d8c",
+                    "sys": {
+                        "MethodName": "orders",
+                        "UtcNow": "2024-05-01T12:34:56.789Z",
+                        "RandGuid": "0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0"
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            context,
+            InvocationContext {
+                invocation_id: Some("2e7a1c5b-7d3e-4a5f-9d1c-3b2a1f0e9d8c".to_string()),
+                function_name: Some("orders".to_string()),
+                utc_now: Some("2024-05-01T12:34:56.789Z".to_string()),
+                traceparent: Some(
+                    "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_string()
+                ),
+            }
+        );
+
+        let context =
+            InvocationContext::from_request(r#"{ "Data": { "myQueueItem": "\"hello\"" } }"#)
+                .unwrap();
+        assert_eq!(context, InvocationContext::default());
+        assert!(InvocationContext::from_request("not json").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Rendering the key-values of the records.
+use std::fmt::Write as _;
+
+use log::kv::{Error, Key, Value, VisitSource, VisitValue};
+
+use crate::context::write_field;
+
+/// How the key-values of the records, such as `log::info!(order_id = 42; "shipped")`, are rendered into
+/// the message, see [Builder::kv_style](crate::Builder::kv_style). They are rendered before the message is
+/// checked for `warn`, so a key such as `warning_count` is transformed like the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KvStyle {
+    /// `key=value` pairs after the message, such as `shipped order_id=42 carrier="Two Words"`.
+    #[default]
+    Suffix,
+    /// A JSON object after the message, such as `shipped {"order_id":42,"carrier":"Two Words"}`.
+    Json,
+    /// Not rendered, for example because the [format](crate::Builder::format) renders them.
+    Omit,
+}
+
+/// Appends the key-values of `record` to `msg` in `style`.
+pub(crate) fn render(style: KvStyle, record: &log::Record, mut msg: String) -> String {
+    let source = record.key_values();
+    if style == KvStyle::Omit || source.count() == 0 {
+        return msg;
+    }
+    msg.push(' ');
+    if style == KvStyle::Json {
+        msg.push('{');
+    }
+    let _ = source.visit(&mut Pairs {
+        style,
+        out: &mut msg,
+        first: true,
+    });
+    if style == KvStyle::Json {
+        msg.push('}');
+    }
+    msg
+}
+
+struct Pairs<'a> {
+    style: KvStyle,
+    out: &'a mut String,
+    first: bool,
+}
+
+impl<'kvs> VisitSource<'kvs> for Pairs<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        if !self.first {
+            self.out.push(if self.style == KvStyle::Json {
+                ','
+            } else {
+                ' '
+            });
+        }
+        self.first = false;
+        if self.style == KvStyle::Json {
+            write_json_string(self.out, key.as_str());
+            self.out.push(':');
+            value.visit(JsonValue(self.out))
+        } else {
+            write_field(self.out, key.as_str(), &value.to_string());
+            Ok(())
+        }
+    }
+}
+
+struct JsonValue<'a>(&'a mut String);
+
+impl<'v> VisitValue<'v> for JsonValue<'_> {
+    fn visit_any(&mut self, value: Value) -> Result<(), Error> {
+        write_json_string(self.0, &value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0.push_str("null");
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_u128(&mut self, value: u128) -> Result<(), Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_i128(&mut self, value: i128) -> Result<(), Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), Error> {
+        // JSON has no representation of NaN and the infinities.
+        if value.is_finite() {
+            let _ = write!(self.0, "{}", value);
+        } else {
+            self.0.push_str("null");
+        }
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), Error> {
+        let _ = write!(self.0, "{}", value);
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), Error> {
+        write_json_string(self.0, value);
+        Ok(())
+    }
+}
+
+fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if ch < ' ' => {
+                let _ = write!(out, "\\u{:04x}", ch as u32);
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::kv::Source;
+
+    fn render_with(style: KvStyle, kvs: &dyn Source) -> String {
+        let record = log::Record::builder()
+            .args(format_args!("shipped"))
+            .key_values(kvs)
+            .build();
+        render(style, &record, record.args().to_string())
+    }
+
+    #[test]
+    fn styles() {
+        let kvs: &[(&str, Value)] = &[
+            ("order_id", Value::from(42u64)),
+            ("carrier", Value::from("Two Words")),
+            ("late", Value::from(false)),
+            ("weight", Value::from(1.5f64)),
+            ("note", Value::from("a \"quoted\"\nline")),
+        ];
+        assert_eq!(
+            render_with(KvStyle::Suffix, &kvs),
+            r#"shipped order_id=42 carrier="Two Words" late=false weight=1.5 note="a \"quoted\"\nline""#
+        );
+        assert_eq!(
+            render_with(KvStyle::Json, &kvs),
+            r#"shipped {"order_id":42,"carrier":"Two Words","late":false,"weight":1.5,"note":"a \"quoted\"\nline"}"#
+        );
+        assert_eq!(render_with(KvStyle::Omit, &kvs), "shipped");
+        assert_eq!(
+            render_with(KvStyle::Json, &[("debug", Value::from_debug(&Some(1)))]),
+            r#"shipped {"debug":"Some(1)"}"#
+        );
+        let none: &[(&str, Value)] = &[];
+        assert_eq!(render_with(KvStyle::Json, &none), "shipped");
+    }
+}
@@ -0,0 +1,227 @@
+//! Logging the events of `tracing`.
+use std::fmt::{self, Write as _};
+use std::sync::Arc;
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::AfchLogger;
+
+/// A [Layer] logging the events of `tracing` by an [AfchLogger], so that they are written and transformed
+/// the same way as the records of `log`, without the `tracing-log` bridge.
+///
+/// Each line starts with the spans of the event from the root, with their fields, followed by the message
+/// and the fields of the event, such as `invocation{id=42}:query{table="orders"}: failed retries=3`. The
+/// levels, directives and filter of the logger apply to the events as to the records of the same level and
+/// target.
+///
+/// The logger is shut down by [shutdown](crate::shutdown) while the layer is alive, even though the
+/// layer is owned by the subscriber.
+pub struct AfchLayer {
+    logger: Arc<AfchLogger>,
+}
+
+impl AfchLayer {
+    /// Takes an [AfchLogger], or an [Arc] of one to keep a handle on it.
+    pub fn new(logger: impl Into<Arc<AfchLogger>>) -> AfchLayer {
+        AfchLayer {
+            logger: crate::logger::share(logger),
+        }
+    }
+
+    /// The logger writing the events, for example to [flush](log::Log::flush) or
+    /// [shut down](AfchLogger::shutdown) it.
+    pub fn logger(&self) -> &Arc<AfchLogger> {
+        &self.logger
+    }
+}
+
+impl Default for AfchLayer {
+    fn default() -> Self {
+        AfchLayer::new(AfchLogger::new())
+    }
+}
+
+impl std::fmt::Debug for AfchLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AfchLayer")
+            .field("logger", &self.logger)
+            .finish()
+    }
+}
+
+// The formatted fields of a span, kept in its extensions.
+struct SpanFields(String);
+
+#[derive(Default)]
+struct FieldWriter {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldWriter {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+pub(crate) fn log_level(level: &tracing::Level) -> log::Level {
+    match *level {
+        tracing::Level::ERROR => log::Level::Error,
+        tracing::Level::WARN => log::Level::Warn,
+        tracing::Level::INFO => log::Level::Info,
+        tracing::Level::DEBUG => log::Level::Debug,
+        tracing::Level::TRACE => log::Level::Trace,
+    }
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for AfchLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut writer = FieldWriter::default();
+        attrs.record(&mut writer);
+        span.extensions_mut().insert(SpanFields(writer.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut writer = FieldWriter {
+                message: String::new(),
+                fields: std::mem::take(fields),
+            };
+            values.record(&mut writer);
+            *fields = writer.fields;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let log_metadata = log::Metadata::builder()
+            .level(log_level(metadata.level()))
+            .target(metadata.target())
+            .build();
+        if !log::Log::enabled(&*self.logger, &log_metadata) {
+            return;
+        }
+
+        let mut line = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                line.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(line, "{{{}}}", fields);
+                    }
+                }
+                line.push(':');
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+        }
+        let mut writer = FieldWriter::default();
+        event.record(&mut writer);
+        line.push_str(&writer.message);
+        if !writer.fields.is_empty() {
+            if !writer.message.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&writer.fields);
+        }
+
+        log::Log::log(
+            &*self.logger,
+            &log::Record::builder()
+                .metadata(log_metadata)
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .args(format_args!("{}", line))
+                .build(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemorySink;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn with_layer(f: impl FnOnce()) -> (Vec<String>, Vec<String>) {
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let layer = AfchLayer::new(
+            AfchLogger::builder()
+                .info_sink(info.clone())
+                .error_sink(error.clone())
+                .build(),
+        );
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+        (info.take(), error.take())
+    }
+
+    #[test]
+    fn routing_and_transform() {
+        let (info, error) = with_layer(|| {
+            tracing::info!("started");
+            tracing::debug!("hidden");
+            tracing::warn!(attempt = 2, "slow");
+            tracing::error!("failed");
+            tracing::error!("unexpected warning");
+        });
+        assert_eq!(info, ["started"]);
+        assert_eq!(
+            error,
+            [
+                "warning: slow attempt=2",
+                "failed",
+                "base64-encoded log: dW5leHBlY3RlZCB3YXJuaW5n"
+            ]
+        );
+    }
+
+    #[test]
+    fn span_fields() {
+        let (info, error) = with_layer(|| {
+            let invocation =
+                tracing::info_span!("invocation", id = 42, user = tracing::field::Empty);
+            let _invocation = invocation.enter();
+            invocation.record("user", "alice");
+            let query = tracing::info_span!("query", table = "orders");
+            let _query = query.enter();
+            tracing::info!(rows = 3, "done");
+            // The fields are checked for `warn` too.
+            tracing::error!(warnings = 1, "failed");
+        });
+        assert_eq!(
+            info,
+            [r#"invocation{id=42 user="alice"}:query{table="orders"}: done rows=3"#]
+        );
+        assert_eq!(error.len(), 1);
+        assert_eq!(
+            crate::decode::decode_line(&error[0]),
+            Some(Ok(
+                r#"invocation{id=42 user="alice"}:query{table="orders"}: failed warnings=1"#
+                    .to_string()
+            ))
+        );
+    }
+}
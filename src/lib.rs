@@ -11,51 +11,162 @@
 //! So the strategy is, for error-level log, if `warn` occurs, base64-encode it, if the encoded string still contains `warn`,
 //! base-encode again, and if the twice-encoded string still contains `warn` (which should be impossible), log an error explain that the
 //! following warning is error, then log it as a warning. For warning-level log, if `warn` does not occur, add a `warning:` prefix.
+//! As the runtime checks every line separately, a multi-line warning gets the prefix on each line that does not contain `warn`.
 //! 
 //! You can initialize the log by [init]. You can also implement your own transform logic by implementing
-//! [Transform] trait and passing it to [init_transform].
+//! [Transform] trait and passing it to [init_transform], or [RecordTransform] trait to access the record and
+//! avoid copying the messages. [HexTransform] is an alternative to [DefaultTransform]
+//! whose encoded error logs can never contain `warn`, and [EscapeTransform] keeps them readable by only
+//! escaping the `warn` occurrences, or [UnicodeTransform] by breaking them with invisible or look-alike
+//! characters. With the `compress` feature, `CompressTransform` compresses large error logs before
+//! encoding them. [EnvelopeTransform] encodes them with the metadata of their records. For more options, such as the maximum level,
+//! formatting, filtering, output [Sink]s or writing by a background thread, use [AfchLogger::builder]. The levels can be set per target by
+//! `RUST_LOG` style [Directives], or follow the levels configured for the Azure Function host by
+//! [HostLogLevels].
+//!
+//! To tell the logs of concurrent invocations apart, scope the handling of an invocation by an [InvocationContext],
+//! which prefixes the lines by the function name and invocation ID. With the `invocation-json` feature, it can
+//! be read from the request of the host.
+//!
+//! Fields such as a tenant ID can be attached to every record logged inside a scope by [LogContext], on a
+//! thread or across the `.await` points of a future.
+//!
+//! With the `kv` feature, the key-values of the records are rendered after the message, see `KvStyle`.
+//!
+//! To restore the order of the logs across stdout and stderr, [Builder::sequence_numbers] prefixes each line by
+//! a [SequenceKey], and [sort_by_sequence] sorts the exported logs.
+//!
+//! With the `tracing` feature, `AfchLayer` logs the events of `tracing` the same way, with the fields of their spans,
+//! and `AfchMakeWriter` routes the output of `tracing_subscriber::fmt` by the levels of the events.
+//!
+//! Call [shutdown] before the process exits, so that no log is left unwritten.
+//!
+//! To return the logs of an invocation in the `Logs` array of the custom handler response instead of
+//! printing them, enter a [LogCollector] while handling the invocation, or scope the handling future by it.
+//!
+//! The [decode] module restores the error logs encoded by [DefaultTransform], [HexTransform], [EnvelopeTransform] and,
+//! with the `compress` feature, `CompressTransform`. The `afch-decode` binary, enabled by the `decode-cli` feature,
+//! applies it to exported logs.
+mod background;
+mod collector;
+#[cfg(feature = "compress")]
+mod compress;
+mod context;
+pub mod decode;
+mod envelope;
+pub mod escape;
+mod filter;
+mod hex;
+mod host;
+mod invocation;
+#[cfg(feature = "kv")]
+mod kv;
+#[cfg(feature = "tracing")]
+mod layer;
+mod logger;
+mod scope;
+mod sequence;
+#[cfg(all(feature = "signal", unix))]
+mod signal;
+mod sink;
+mod unicode;
+#[cfg(feature = "tracing")]
+mod writer;
+
+pub use background::OverflowPolicy;
+pub use collector::{CollectorGuard, LogCollector, Logs};
+#[cfg(feature = "compress")]
+pub use compress::CompressTransform;
+pub use context::{LogContext, LogContextGuard};
+pub use envelope::{Envelope, EnvelopeTransform};
+pub use escape::EscapeTransform;
+pub use filter::{Directives, ParseDirectivesError};
+pub use hex::HexTransform;
+pub use host::{HostLogLevels, FUNCTION_CATEGORY};
+pub use invocation::{InvocationContext, InvocationGuard, INVOCATION_ID_HEADER};
+#[cfg(feature = "kv")]
+pub use kv::KvStyle;
+#[cfg(feature = "tracing")]
+pub use layer::AfchLayer;
+pub use logger::{AfchLogger, Builder};
+pub use scope::{ScopeGuard, Scoped};
+pub use sequence::{sort_by_sequence, SequenceKey};
+#[cfg(all(feature = "signal", unix))]
+pub use signal::{drain_on_signal, shutdown_on_signal};
+pub use sink::{
+    MemorySink, Sink, StderrSink, StdoutSink, WriteErrorCounter, WriteErrorPolicy, WriterSink,
+};
+pub use unicode::UnicodeTransform;
+#[cfg(feature = "tracing")]
+pub use writer::{AfchMakeWriter, AfchWriter};
+
+use std::borrow::Cow;
+
 const WARN: [char; 4] = ['w', 'a', 'r', 'n'];
 
+/// The runtime classifies every line written to stderr on its own, so a transform has to make sure
+/// that each line of its result gets the intended level.
 pub trait Transform {
-    /// Transform the error log message that contains `warn` (case insensitive).
+    /// Transform the error log message that contains `warn` (case insensitive) in any of its lines.
+    /// The message may have multiple lines, none of the lines of the result should contain `warn`.
     fn transform_error(&self, msg: String) -> String;
-    /// Transform the warning log message that does not contain `warn` (case insensitive).
+    /// Transform a line of the warning log message that does not contain `warn` (case insensitive).
+    /// It is called for each such line, the result should be a single line containing `warn`.
     fn transform_warning(&self, msg: String) -> String;
 }
-struct Logger<T>(T);
-impl<T: Transform + Send + Sync> log::Log for Logger<T> {
-    fn enabled(&self, m: &log::Metadata) -> bool {
-        m.level() <= log::Level::Info
-    }
-
-    fn log(&self, record: &log::Record) {
-        match record.level() {
-            log::Level::Error => {
-                let mut log = record.args().to_string();
-                if contains_warn(&log) {
-                    log = self.0.transform_error(log);
-                }
 
-                eprintln!("{}", log);
-            },
-            log::Level::Warn => {
-                let mut log = record.args().to_string();
-                if !contains_warn(&log) {
-                    log = self.0.transform_warning(log);
-                }
+/// A transform with access to the record being logged, that can return the message without copying it.
+///
+/// It is implemented for every [Transform], so implement it instead of [Transform] only to access the
+/// record, as [EnvelopeTransform] does, or to avoid the allocations. The rules of the results are the same
+/// as [Transform]'s.
+///
+/// ```
+/// use std::borrow::Cow;
+/// use afch_logger::{DefaultTransform, RecordTransform, Transform};
+///
+/// /// Leaves the errors of the `audit` target as warnings.
+/// struct AuditAsWarning;
+///
+/// impl RecordTransform for AuditAsWarning {
+///     fn rewrite_error<'a>(&self, record: &log::Record, msg: &'a str) -> Cow<'a, str> {
+///         if record.target() == "audit" {
+///             Cow::Borrowed(msg)
+///         } else {
+///             Cow::Owned(DefaultTransform.transform_error(msg.to_string()))
+///         }
+///     }
+///
+///     fn rewrite_warning<'a>(&self, _: &log::Record, line: &'a str) -> Cow<'a, str> {
+///         Cow::Owned(format!("warning: {}", line))
+///     }
+/// }
+///
+/// afch_logger::AfchLogger::builder().transform(AuditAsWarning).build();
+/// ```
+pub trait RecordTransform {
+    /// Transform the error log message that contains `warn`, see [Transform::transform_error].
+    fn rewrite_error<'a>(&self, record: &log::Record, msg: &'a str) -> Cow<'a, str>;
+    /// Transform a line of the warning log message that does not contain `warn`, see
+    /// [Transform::transform_warning].
+    fn rewrite_warning<'a>(&self, record: &log::Record, line: &'a str) -> Cow<'a, str>;
+}
 
-                eprintln!("{}", log);
-            }
-            log::Level::Info => println!("{}", record.args()),
-            _ => {}
-        }
-        
+impl<T: Transform + ?Sized> RecordTransform for T {
+    fn rewrite_error<'a>(&self, _: &log::Record, msg: &'a str) -> Cow<'a, str> {
+        Cow::Owned(self.transform_error(msg.to_string()))
     }
 
-    fn flush(&self) {}
+    fn rewrite_warning<'a>(&self, _: &log::Record, line: &'a str) -> Cow<'a, str> {
+        Cow::Owned(self.transform_warning(line.to_string()))
+    }
 }
+
 /// Returns true if the message contains `warn` (case insensitive).
 pub fn contains_warn(s: &str) -> bool {
+    // Number of characters of `warn` matched so far. `warn` has no proper prefix that is also
+    // a suffix, so on a mismatch the only partial match that can survive is the current character
+    // starting a new `w`.
     let mut warn_ptr = 0;
     for ch in s.chars() {
         if ch.eq_ignore_ascii_case(&WARN[warn_ptr]) {
@@ -63,6 +174,8 @@ pub fn contains_warn(s: &str) -> bool {
                 return true
             }
             warn_ptr += 1;
+        } else if ch.eq_ignore_ascii_case(&WARN[0]) {
+            warn_ptr = 1;
         } else {
             warn_ptr = 0;
         }
@@ -76,14 +189,14 @@ impl Transform for DefaultTransform {
         let mut transformed = base64::encode(&msg);
 
         if !contains_warn(&transformed) {
-            "base64-encoded log: ".to_string() + &transformed
+            decode::BASE64_PREFIX.to_string() + &transformed
         } else {
             transformed = base64::encode(transformed);
             if !contains_warn(&transformed) {
-                "base64-encoded-twice log: ".to_string() + &transformed
+                decode::BASE64_TWICE_PREFIX.to_string() + &transformed
             } else {
                 // Should be impossible.
-                "The following error log has to be logged as Warning: \n".to_string() + &msg
+                decode::AS_WARNING_NOTICE.to_string() + "\n" + &msg
             }
         }
     }
@@ -93,16 +206,39 @@ impl Transform for DefaultTransform {
     }
 }
 
+/// Initializes the logger with [DefaultTransform].
+///
+/// Panics if a global logger has already been set, use [Builder::try_init] to handle the error.
 pub fn init() {
     init_transform(DefaultTransform);
 }
 
-pub fn init_transform<T: Transform + 'static + Send + Sync>(transform: T) {
-    log::set_logger(Box::leak(Box::new(Logger(transform))))
+/// Initializes the logger with `transform`, any [Transform] or [RecordTransform].
+///
+/// Panics if a global logger has already been set, use [Builder::try_init] to handle the error.
+pub fn init_transform<T: RecordTransform + 'static + Send + Sync>(transform: T) {
+    AfchLogger::builder()
+        .transform(transform)
+        .try_init()
         .expect("Failed to initialize logger");
-    log::set_max_level(log::LevelFilter::Info);
 }
 
+/// Shuts down the logger installed by this crate, see [AfchLogger::shutdown], or flushes the global logger
+/// if it is another one. With the `tracing` feature, the loggers of the live `AfchLayer`s and
+/// `AfchMakeWriter`s are shut down too.
+///
+/// Call it before `std::process::exit`, which does not run destructors. With the `signal` feature,
+/// `shutdown_on_signal` and `drain_on_signal` call it when the process is terminated on Unix.
+pub fn shutdown() {
+    match logger::global() {
+        Some(logger) => logger.shutdown(),
+        None => log::logger().flush(),
+    }
+    #[cfg(feature = "tracing")]
+    for logger in logger::shared() {
+        logger.shutdown();
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -128,5 +264,64 @@ mod tests {
     fn split_warn() {
         assert!(!contains_warn("wa#rn"));
     }
-}
+    #[test]
+    fn overlapping_prefix_warn() {
+        assert!(contains_warn("wwarn"));
+        assert!(contains_warn("WWARNING"));
+        assert!(contains_warn("wawarn"));
+        assert!(contains_warn("warwarn"));
+    }
 
+    fn reference_contains_warn(s: &str) -> bool {
+        s.to_lowercase().contains("warn")
+    }
+
+    #[test]
+    fn exhaustive_short_strings() {
+        const ALPHABET: [char; 9] = ['w', 'a', 'r', 'n', 'W', 'A', 'R', 'N', 'x'];
+        const MAX_LEN: u32 = 6;
+
+        let mut s = String::new();
+        for len in 0..=MAX_LEN {
+            for mut index in 0..ALPHABET.len().pow(len) {
+                s.clear();
+                for _ in 0..len {
+                    s.push(ALPHABET[index % ALPHABET.len()]);
+                    index /= ALPHABET.len();
+                }
+                assert_eq!(contains_warn(&s), reference_contains_warn(&s), "{:?}", s);
+            }
+        }
+    }
+
+    /// Strings of `alphabet` shorter than `max_len`, generated by a fixed-seed xorshift so that failures are
+    /// reproducible.
+    pub(crate) fn random_strings(
+        seed: u64,
+        alphabet: &[char],
+        max_len: u64,
+    ) -> impl Iterator<Item = String> + '_ {
+        let mut state = seed;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        std::iter::repeat_with(move || {
+            let len = next() % max_len;
+            (0..len)
+                .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+                .collect()
+        })
+    }
+
+    #[test]
+    fn random_strings_contain_warn() {
+        const ALPHABET: [char; 12] = ['w', 'a', 'r', 'n', 'W', 'A', 'R', 'N', ' ', '\n', 'é', '界'];
+
+        for s in random_strings(0x2545_f491_4f6c_dd1d, &ALPHABET, 40).take(20_000) {
+            assert_eq!(contains_warn(&s), reference_contains_warn(&s), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,1018 @@
+use std::borrow::Cow;
+use std::io::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "tracing")]
+use std::sync::{Mutex, PoisonError, Weak};
+
+use log::{LevelFilter, Metadata, Record, SetLoggerError};
+
+use crate::background::{Background, Output};
+use crate::{
+    collector, contains_warn, context, invocation, DefaultTransform, Directives, HostLogLevels,
+    OverflowPolicy, ParseDirectivesError, RecordTransform, SequenceKey, Sink, StderrSink,
+    StdoutSink, WriteErrorCounter, WriteErrorPolicy, WriterSink,
+};
+
+type FormatFn = Box<dyn Fn(&Record) -> String + Send + Sync>;
+type FilterFn = Box<dyn Fn(&Metadata) -> bool + Send + Sync>;
+type BoxSink = Box<dyn Sink>;
+
+// The logger installed by [Builder::try_init], for [crate::shutdown].
+static GLOBAL: OnceLock<AfchLogger> = OnceLock::new();
+
+pub(crate) fn global() -> Option<&'static AfchLogger> {
+    GLOBAL.get()
+}
+
+// The loggers owned by the subscribers of `tracing`, out of reach otherwise, for [crate::shutdown].
+#[cfg(feature = "tracing")]
+static SHARED: Mutex<Vec<Weak<AfchLogger>>> = Mutex::new(Vec::new());
+
+// Registers the logger for [crate::shutdown], as long as it is alive.
+#[cfg(feature = "tracing")]
+pub(crate) fn share(logger: impl Into<Arc<AfchLogger>>) -> Arc<AfchLogger> {
+    let logger = logger.into();
+    let mut shared = SHARED.lock().unwrap_or_else(PoisonError::into_inner);
+    shared.retain(|weak| weak.strong_count() > 0);
+    let weak = Arc::downgrade(&logger);
+    if !shared.iter().any(|other| other.ptr_eq(&weak)) {
+        shared.push(weak);
+    }
+    logger
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn shared() -> Vec<Arc<AfchLogger>> {
+    let shared = SHARED.lock().unwrap_or_else(PoisonError::into_inner);
+    shared.iter().filter_map(Weak::upgrade).collect()
+}
+
+// The global logger set by [Builder::try_init], forwarding to [GLOBAL]. The [AfchLogger] is only built once
+// this is set, so that a failed call leaks nothing.
+struct Global;
+
+impl log::Log for Global {
+    fn enabled(&self, m: &Metadata) -> bool {
+        global().is_some_and(|logger| logger.enabled(m))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = global() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = global() {
+            logger.flush();
+        }
+    }
+}
+
+/// The logger, configured by [AfchLogger::builder].
+///
+/// It implements [log::Log], so besides being installed globally by [Builder::try_init], a value built
+/// by [Builder::build] can be owned directly, for example to be wrapped by another logger or used in tests.
+/// Note that when wrapped, the global maximum level is up to the outer logger, see [AfchLogger::max_level].
+pub struct AfchLogger {
+    max_level: LevelFilter,
+    transform: Box<dyn RecordTransform + Send + Sync>,
+    format: FormatFn,
+    directives: Option<Directives>,
+    filter: Option<FilterFn>,
+    outputs: Arc<Outputs>,
+    background: Option<Background>,
+    debug_prefix: String,
+    trace_prefix: String,
+    sequence: Option<AtomicU64>,
+    #[cfg(feature = "kv")]
+    kv_style: crate::KvStyle,
+}
+
+// The sinks, shared with the background writer if any.
+struct Outputs {
+    info: BoxSink,
+    error: BoxSink,
+    verbose: Option<BoxSink>,
+    write_error_policy: WriteErrorPolicy,
+    write_errors: WriteErrorCounter,
+}
+
+impl Outputs {
+    fn write(&self, output: Output, line: &str) {
+        let sink = match output {
+            Output::Info => &self.info,
+            Output::Error => &self.error,
+            Output::Verbose => self.verbose.as_ref().unwrap_or(&self.info),
+        };
+        // Logging should never bring down the handler, the failure is handled by the policy.
+        let _ = self
+            .write_error_policy
+            .write(sink.as_ref(), line, &self.write_errors);
+    }
+
+    fn flush(&self) {
+        for sink in [Some(&self.info), Some(&self.error), self.verbose.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = sink.flush();
+        }
+    }
+}
+
+impl AfchLogger {
+    /// Creates a logger with the default configuration, see [Builder].
+    pub fn new() -> AfchLogger {
+        Builder::default().build()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// The maximum level of the records this logger logs.
+    pub fn max_level(&self) -> LevelFilter {
+        self.max_level
+    }
+
+    /// The counter of the lines this logger has failed to write, see [WriteErrorPolicy].
+    pub fn write_error_counter(&self) -> &WriteErrorCounter {
+        &self.outputs.write_errors
+    }
+
+    /// Writes the lines queued for the background writer if any and stops it, then flushes the sinks.
+    /// The records logged afterwards are written directly. Call it before the process exits, as the
+    /// queued lines are lost otherwise.
+    pub fn shutdown(&self) {
+        if let Some(background) = &self.background {
+            background.close();
+        }
+        self.outputs.flush();
+    }
+
+    /// Writes the record regardless of the levels and filters.
+    pub(crate) fn write_record(&self, record: &Record) {
+        let msg = (self.format)(record);
+        #[cfg(feature = "kv")]
+        let msg = crate::kv::render(self.kv_style, record, msg);
+        // The fields of the context are part of the record, so they are collected as the key-values are.
+        let msg = match context::current_prefix() {
+            Some(prefix) => prefix_lines(&prefix, &msg),
+            None => msg,
+        };
+        if collector::collect_with(|| format!("{}: {}", record.level(), msg)) {
+            return;
+        }
+        // The prefixes are part of the message, so they are checked for `warn` and transformed too.
+        let msg = match invocation::active_prefix() {
+            Some(prefix) => prefix_lines(&prefix, &msg),
+            None => msg,
+        };
+
+        let (output, line) = match record.level() {
+            log::Level::Error => (Output::Error, self.render_error(record, &msg)),
+            log::Level::Warn => (Output::Error, self.render_warning(record, &msg)),
+            log::Level::Info => (Output::Info, Cow::Borrowed(msg.as_str())),
+            level @ (log::Level::Debug | log::Level::Trace) => (
+                Output::Verbose,
+                Cow::Owned(self.render_verbose(level, &msg)),
+            ),
+        };
+        // The key is prefixed after the transform, it never contains `warn`.
+        let line = match &self.sequence {
+            Some(counter) => Cow::Owned(SequenceKey::next(counter).prefix_lines(&line)),
+            None => line,
+        };
+        self.write_line(output, line);
+    }
+
+    fn write_line(&self, output: Output, line: Cow<str>) {
+        let line = match &self.background {
+            Some(background) => match background.push(output, line.into_owned()) {
+                Ok(()) => return,
+                Err(line) => Cow::Owned(line),
+            },
+            None => line,
+        };
+        self.outputs.write(output, &line)
+    }
+
+    fn render_error<'a>(&self, record: &Record, msg: &'a str) -> Cow<'a, str> {
+        if contains_warn(msg) {
+            self.transform.rewrite_error(record, msg)
+        } else {
+            Cow::Borrowed(msg)
+        }
+    }
+
+    fn render_warning<'a>(&self, record: &Record, msg: &'a str) -> Cow<'a, str> {
+        if !msg.contains('\n') {
+            return if contains_warn(msg) {
+                Cow::Borrowed(msg)
+            } else {
+                self.transform.rewrite_warning(record, msg)
+            };
+        }
+
+        let lines: Vec<_> = msg
+            .split('\n')
+            .map(|line| {
+                if contains_warn(line) {
+                    Cow::Borrowed(line)
+                } else {
+                    self.transform.rewrite_warning(record, line)
+                }
+            })
+            .collect();
+        Cow::Owned(lines.join("\n"))
+    }
+
+    fn render_verbose(&self, level: log::Level, msg: &str) -> String {
+        let prefix = if level == log::Level::Debug {
+            &self.debug_prefix
+        } else {
+            &self.trace_prefix
+        };
+        prefix_lines(prefix, msg)
+    }
+}
+
+pub(crate) fn prefix_lines(prefix: &str, msg: &str) -> String {
+    msg.split('\n')
+        .map(|line| format!("{}{}", prefix, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Default for AfchLogger {
+    fn default() -> Self {
+        AfchLogger::new()
+    }
+}
+
+impl std::fmt::Debug for AfchLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AfchLogger")
+            .field("max_level", &self.max_level)
+            .finish_non_exhaustive()
+    }
+}
+
+impl log::Log for AfchLogger {
+    fn enabled(&self, m: &Metadata) -> bool {
+        m.level() <= self.max_level
+            && self.directives.as_ref().is_none_or(|d| d.enabled(m))
+            && self.filter.as_ref().is_none_or(|filter| filter(m))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.write_record(record);
+        }
+    }
+
+    /// Waits until the background writer, if any, has written all the queued lines, then flushes the sinks.
+    fn flush(&self) {
+        if let Some(background) = &self.background {
+            background.drain();
+        }
+        self.outputs.flush();
+    }
+}
+
+/// Builder of [AfchLogger].
+///
+/// By default it logs Info and above with [DefaultTransform], writing information logs to stdout and
+/// warning and error logs to stderr. If the maximum level allows them, debug and trace logs are written
+/// to the same output as information logs, each line prefixed by `debug: ` or `trace: `.
+pub struct Builder {
+    max_level: Option<LevelFilter>,
+    transform: Option<Box<dyn RecordTransform + Send + Sync>>,
+    format: Option<FormatFn>,
+    directives: Option<Directives>,
+    filter: Option<FilterFn>,
+    info_sink: Option<BoxSink>,
+    error_sink: Option<BoxSink>,
+    verbose_sink: Option<BoxSink>,
+    debug_prefix: String,
+    trace_prefix: String,
+    write_error_policy: WriteErrorPolicy,
+    write_errors: Option<WriteErrorCounter>,
+    background: Option<(usize, OverflowPolicy)>,
+    sequence_numbers: bool,
+    #[cfg(feature = "kv")]
+    kv_style: crate::KvStyle,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            max_level: None,
+            transform: None,
+            format: None,
+            directives: None,
+            filter: None,
+            info_sink: None,
+            error_sink: None,
+            verbose_sink: None,
+            debug_prefix: "debug: ".to_string(),
+            trace_prefix: "trace: ".to_string(),
+            write_error_policy: WriteErrorPolicy::default(),
+            write_errors: None,
+            background: None,
+            sequence_numbers: false,
+            #[cfg(feature = "kv")]
+            kv_style: crate::KvStyle::default(),
+        }
+    }
+}
+
+impl Builder {
+    /// Sets the maximum level of the records to log. By default it is Info, or the most verbose level
+    /// of the [directives](Builder::directives) if they are set.
+    pub fn max_level(mut self, level: LevelFilter) -> Self {
+        self.max_level = Some(level);
+        self
+    }
+
+    /// Sets the transform applied to warning and error logs, [DefaultTransform] by default. It can be
+    /// any [Transform](crate::Transform) or [RecordTransform].
+    pub fn transform<T: RecordTransform + Send + Sync + 'static>(mut self, transform: T) -> Self {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Sets how a record is formatted into a message, before any transform. By default only the
+    /// arguments of the record are used.
+    pub fn format<F: Fn(&Record) -> String + Send + Sync + 'static>(mut self, format: F) -> Self {
+        self.format = Some(Box::new(format));
+        self
+    }
+
+    /// Sets the level of each target by [Directives], in addition to the maximum level.
+    pub fn directives(mut self, directives: Directives) -> Self {
+        self.directives = Some(directives);
+        self
+    }
+
+    /// Logs at the level the Azure Function host is configured with for the function logs, see
+    /// [HostLogLevels]. It replaces the directives.
+    pub fn host_levels(self, levels: &HostLogLevels) -> Self {
+        self.directives(levels.directives())
+    }
+
+    /// Reads the [Directives] from the environment variable `name`, such as `RUST_LOG`, if it is set.
+    pub fn env_directives(self, name: &str) -> Result<Self, ParseDirectivesError> {
+        Ok(match Directives::from_env(name)? {
+            Some(directives) => self.directives(directives),
+            None => self,
+        })
+    }
+
+    /// Only logs the records whose metadata passes `filter`, in addition to the maximum level.
+    pub fn filter<F: Fn(&Metadata) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Sets the [Sink] of information logs, [StdoutSink] by default.
+    pub fn info_sink<S: Sink + 'static>(mut self, sink: S) -> Self {
+        self.info_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Sets the [Sink] of warning and error logs, [StderrSink] by default.
+    pub fn error_sink<S: Sink + 'static>(mut self, sink: S) -> Self {
+        self.error_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Sets the [Sink] of debug and trace logs, the one of information logs by default.
+    pub fn verbose_sink<S: Sink + 'static>(mut self, sink: S) -> Self {
+        self.verbose_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Writes information logs to `output`, see [Builder::info_sink].
+    pub fn info_output<W: Write + Send + 'static>(self, output: W) -> Self {
+        self.info_sink(WriterSink::new(output))
+    }
+
+    /// Writes warning and error logs to `output`, see [Builder::error_sink].
+    pub fn error_output<W: Write + Send + 'static>(self, output: W) -> Self {
+        self.error_sink(WriterSink::new(output))
+    }
+
+    /// Writes debug and trace logs to `output`, see [Builder::verbose_sink].
+    pub fn verbose_output<W: Write + Send + 'static>(self, output: W) -> Self {
+        self.verbose_sink(WriterSink::new(output))
+    }
+
+    /// Sets the prefix of each line of debug logs, `debug: ` by default.
+    pub fn debug_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.debug_prefix = prefix.into();
+        self
+    }
+
+    /// Sets the prefix of each line of trace logs, `trace: ` by default.
+    pub fn trace_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.trace_prefix = prefix.into();
+        self
+    }
+
+    /// Sets what to do when a sink fails to write a line, [WriteErrorPolicy::Drop] by default.
+    pub fn write_error_policy(mut self, policy: WriteErrorPolicy) -> Self {
+        self.write_error_policy = policy;
+        self
+    }
+
+    /// Counts the lines failed to write by `counter`, so that it can be queried after the logger is installed.
+    pub fn write_error_counter(mut self, counter: WriteErrorCounter) -> Self {
+        self.write_errors = Some(counter);
+        self
+    }
+
+    /// Writes the lines by a background thread, so that logging does not wait for the outputs. Up to
+    /// `capacity` lines are queued, when the queue is full `policy` applies. If lines are dropped, a
+    /// warning with their number is written to the error output. [log::Log::flush] waits until the
+    /// queued lines are written.
+    pub fn background(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.background = Some((capacity, policy));
+        self
+    }
+
+    /// Prefixes each line by a [SequenceKey], the sequence number and time of the record, so that the order
+    /// of the logs across stdout and stderr can be restored by [sort_by_sequence](crate::sort_by_sequence).
+    pub fn sequence_numbers(mut self, enabled: bool) -> Self {
+        self.sequence_numbers = enabled;
+        self
+    }
+
+    /// Sets how the key-values of the records are rendered after the message, [KvStyle::Suffix](crate::KvStyle::Suffix)
+    /// by default.
+    #[cfg(feature = "kv")]
+    pub fn kv_style(mut self, style: crate::KvStyle) -> Self {
+        self.kv_style = style;
+        self
+    }
+
+    /// Installs the logger as the global logger, which [crate::shutdown] shuts down.
+    ///
+    /// Fails if a global logger has already been set.
+    pub fn try_init(self) -> Result<(), SetLoggerError> {
+        log::set_logger(&Global)?;
+        // Nothing is logged until the maximum level is set.
+        let logger = GLOBAL.get_or_init(|| self.build());
+        log::set_max_level(logger.max_level());
+        Ok(())
+    }
+
+    /// Builds the logger without installing it.
+    pub fn build(self) -> AfchLogger {
+        let outputs = Arc::new(Outputs {
+            info: self.info_sink.unwrap_or_else(|| Box::new(StdoutSink)),
+            error: self.error_sink.unwrap_or_else(|| Box::new(StderrSink)),
+            verbose: self.verbose_sink,
+            write_error_policy: self.write_error_policy,
+            write_errors: self.write_errors.unwrap_or_default(),
+        });
+        let background = self.background.map(|(capacity, policy)| {
+            let outputs = outputs.clone();
+            Background::spawn(capacity, policy, move |output, line| {
+                outputs.write(output, line)
+            })
+        });
+        AfchLogger {
+            max_level: self.max_level.unwrap_or_else(|| {
+                self.directives
+                    .as_ref()
+                    .map_or(LevelFilter::Info, Directives::max_level)
+            }),
+            transform: self.transform.unwrap_or_else(|| Box::new(DefaultTransform)),
+            format: self
+                .format
+                .unwrap_or_else(|| Box::new(|record| record.args().to_string())),
+            directives: self.directives,
+            filter: self.filter,
+            outputs,
+            background,
+            debug_prefix: self.debug_prefix,
+            trace_prefix: self.trace_prefix,
+            sequence: self.sequence_numbers.then(|| AtomicU64::new(0)),
+            #[cfg(feature = "kv")]
+            kv_style: self.kv_style,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemorySink;
+    use std::io;
+    use std::sync::Arc;
+
+    #[test]
+    fn composable() {
+        struct Wrapper(Vec<Box<dyn log::Log>>);
+        impl log::Log for Wrapper {
+            fn enabled(&self, m: &Metadata) -> bool {
+                self.0.iter().any(|logger| logger.enabled(m))
+            }
+            fn log(&self, record: &Record) {
+                self.0.iter().for_each(|logger| logger.log(record))
+            }
+            fn flush(&self) {
+                self.0.iter().for_each(|logger| logger.flush())
+            }
+        }
+
+        let output = MemorySink::new();
+        let logger = AfchLogger::builder().info_sink(output.clone()).build();
+        assert_eq!(logger.max_level(), LevelFilter::Info);
+        let wrapper = Wrapper(vec![Box::new(logger)]);
+        log::Log::log(
+            &wrapper,
+            &Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("wrapped"))
+                .build(),
+        );
+        assert_eq!(output.lines(), ["wrapped"]);
+    }
+
+    fn log_all_levels(logger: &AfchLogger) {
+        for level in [
+            log::Level::Error,
+            log::Level::Warn,
+            log::Level::Info,
+            log::Level::Debug,
+            log::Level::Trace,
+        ] {
+            log::Log::log(
+                logger,
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}\nline", level))
+                    .build(),
+            );
+        }
+    }
+
+    #[test]
+    fn verbose_levels() {
+        let info = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .max_level(LevelFilter::Debug)
+            .info_sink(info.clone())
+            .error_sink(MemorySink::new())
+            .build();
+        log_all_levels(&logger);
+        assert_eq!(info.lines(), ["INFO\nline", "debug: DEBUG\ndebug: line"]);
+
+        let info = MemorySink::new();
+        let verbose = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .max_level(LevelFilter::Trace)
+            .info_sink(info.clone())
+            .error_sink(MemorySink::new())
+            .verbose_sink(verbose.clone())
+            .debug_prefix("[D] ")
+            .trace_prefix("[T] ")
+            .build();
+        log_all_levels(&logger);
+        assert_eq!(info.lines(), ["INFO\nline"]);
+        assert_eq!(
+            verbose.lines(),
+            ["[D] DEBUG\n[D] line", "[T] TRACE\n[T] line"]
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let info = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .directives("warn,my_func::db=debug".parse().unwrap())
+            .info_sink(info.clone())
+            .build();
+        assert_eq!(logger.max_level(), LevelFilter::Debug);
+
+        let log = |level, target| {
+            log::Log::log(
+                &logger,
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{}", target))
+                    .build(),
+            )
+        };
+        log(log::Level::Info, "hyper");
+        log(log::Level::Info, "my_func::db::pool");
+        log(log::Level::Debug, "my_func::db");
+        log(log::Level::Trace, "my_func::db");
+        assert_eq!(info.lines(), ["my_func::db::pool", "debug: my_func::db"]);
+    }
+
+    #[test]
+    fn sinks() {
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .max_level(LevelFilter::Debug)
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .build();
+        log_all_levels(&logger);
+        assert_eq!(info.lines(), ["INFO\nline", "debug: DEBUG\ndebug: line"]);
+        assert_eq!(error.lines(), ["ERROR\nline", "WARN\nwarning: line"]);
+    }
+
+    #[test]
+    fn closed_output() {
+        let counter = WriteErrorCounter::new();
+        let logger = AfchLogger::builder()
+            .error_output(ClosedPipe)
+            .write_error_policy(WriteErrorPolicy::RetryOnce)
+            .write_error_counter(counter.clone())
+            .build();
+        log_all_levels(&logger);
+        assert_eq!(counter.get(), 2);
+        assert_eq!(logger.write_error_counter().get(), 2);
+    }
+
+    struct ClosedPipe;
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn try_init_twice() {
+        let _ = AfchLogger::builder().try_init();
+        let sink = Arc::new(MemorySink::new());
+        assert!(AfchLogger::builder()
+            .info_sink(sink.clone())
+            .try_init()
+            .is_err());
+        // Nothing is kept of the logger that failed to install.
+        assert_eq!(Arc::strong_count(&sink), 1);
+    }
+
+    fn record(level: log::Level) -> Record<'static> {
+        Record::builder().level(level).build()
+    }
+
+    #[test]
+    fn multi_line_warning() {
+        let logger = AfchLogger::builder().build();
+        let record = record(log::Level::Warn);
+        assert_eq!(
+            logger.render_warning(&record, "step failed\nwarning details"),
+            "warning: step failed\nwarning details"
+        );
+        assert_eq!(
+            logger.render_warning(&record, "first\n\nthird"),
+            "warning: first\nwarning: \nwarning: third"
+        );
+        assert!(matches!(
+            logger.render_warning(&record, "warned"),
+            Cow::Borrowed("warned")
+        ));
+    }
+
+    #[test]
+    fn multi_line_error() {
+        let logger = AfchLogger::builder().build();
+        let record = record(log::Level::Error);
+        assert_eq!(logger.render_error(&record, "a\nb"), "a\nb");
+        let rendered = logger.render_error(&record, "step failed\nwarning details");
+        assert!(!rendered.contains('\n'));
+        assert!(!contains_warn(&rendered));
+    }
+
+    #[test]
+    fn builder_options() {
+        use log::Log;
+
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .max_level(LevelFilter::Warn)
+            .filter(|m| m.target() != "noisy")
+            .format(|record| format!("[{}] {}", record.target(), record.args()))
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .build();
+
+        let log = |level, target, msg| {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            )
+        };
+        log(log::Level::Info, "app", "ignored by level");
+        log(log::Level::Error, "noisy", "ignored by filter");
+        log(log::Level::Error, "app", "failed");
+        log(log::Level::Warn, "app", "slow");
+
+        assert!(info.lines().is_empty());
+        assert_eq!(error.lines(), ["[app] failed", "warning: [app] slow"]);
+    }
+
+    #[test]
+    fn background() {
+        use log::Log;
+
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .max_level(LevelFilter::Debug)
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .background(8, OverflowPolicy::Block)
+            .build();
+        for _ in 0..50 {
+            log_all_levels(&logger);
+        }
+        logger.flush();
+        assert_eq!(
+            info.lines()[..2],
+            ["INFO\nline", "debug: DEBUG\ndebug: line"]
+        );
+        assert_eq!(info.lines().len(), 50 * 2);
+        assert_eq!(error.lines()[..2], ["ERROR\nline", "WARN\nwarning: line"]);
+        assert_eq!(error.lines().len(), 50 * 2);
+    }
+
+    #[test]
+    fn panicking_sink() {
+        use log::Log;
+
+        struct PanickingSink(MemorySink);
+        impl Sink for PanickingSink {
+            fn write_line(&self, line: &str) -> io::Result<()> {
+                if line == "panic" {
+                    panic!("sink failed");
+                }
+                self.0.write_line(line)
+            }
+        }
+
+        let info = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .info_sink(PanickingSink(info.clone()))
+            .background(1, OverflowPolicy::Block)
+            .build();
+        for msg in ["before", "panic", "after"].iter().cycle().take(30) {
+            logger.log(
+                &Record::builder()
+                    .level(log::Level::Info)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            );
+        }
+        logger.flush();
+        assert_eq!(info.lines().len(), 20);
+        assert_eq!(info.lines()[..2], ["before", "after"]);
+    }
+
+    #[test]
+    fn flush_and_shutdown() {
+        use log::Log;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Clone, Default)]
+        struct FlushCounting(MemorySink, Arc<AtomicUsize>);
+        impl Sink for FlushCounting {
+            fn write_line(&self, line: &str) -> io::Result<()> {
+                self.0.write_line(line)
+            }
+            fn flush(&self) -> io::Result<()> {
+                self.1.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+
+        let info = FlushCounting::default();
+        let logger = AfchLogger::builder()
+            .info_sink(info.clone())
+            .error_sink(info.clone())
+            .verbose_sink(info.clone())
+            .background(4, OverflowPolicy::Block)
+            .build();
+        logger.flush();
+        assert_eq!(info.1.load(Ordering::Relaxed), 3);
+
+        for _ in 0..20 {
+            logger.log(&record(log::Level::Info));
+        }
+        logger.shutdown();
+        assert_eq!(info.0.lines().len(), 20);
+        assert_eq!(info.1.load(Ordering::Relaxed), 6);
+
+        logger.log(&record(log::Level::Info));
+        assert_eq!(info.0.lines().len(), 21);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn shared() {
+        use log::Log;
+
+        struct SlowSink(MemorySink);
+        impl Sink for SlowSink {
+            fn write_line(&self, line: &str) -> io::Result<()> {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                self.0.write_line(line)
+            }
+        }
+
+        let info = MemorySink::new();
+        let logger = super::share(
+            AfchLogger::builder()
+                .info_sink(SlowSink(info.clone()))
+                .background(4, OverflowPolicy::Block)
+                .build(),
+        );
+        let registered = |logger: &Arc<AfchLogger>| {
+            super::shared()
+                .iter()
+                .filter(|shared| Arc::ptr_eq(shared, logger))
+                .count()
+        };
+        // Sharing the same logger again does not register it twice.
+        assert_eq!(registered(&super::share(logger.clone())), 1);
+
+        for _ in 0..20 {
+            logger.log(&record(log::Level::Info));
+        }
+        // The queued lines are written by the shutdown of the registered loggers.
+        crate::shutdown();
+        assert_eq!(info.lines().len(), 20);
+
+        // The registry does not keep the logger alive.
+        let weak = Arc::downgrade(&logger);
+        drop(logger);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .sequence_numbers(true)
+            .build();
+        log_all_levels(&logger);
+
+        let mut lines: Vec<_> = error
+            .lines()
+            .iter()
+            .chain(info.lines().iter())
+            .flat_map(|line| line.split('\n'))
+            .map(str::to_string)
+            .collect();
+        crate::sort_by_sequence(&mut lines, |line| line);
+        let messages: Vec<_> = lines
+            .iter()
+            .map(|line| SequenceKey::parse(line).unwrap().1)
+            .collect();
+        assert_eq!(
+            messages,
+            ["ERROR", "line", "WARN", "warning: line", "INFO", "line"]
+        );
+    }
+
+    #[test]
+    fn invocation_prefix() {
+        use crate::InvocationContext;
+        use log::Log;
+
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .build();
+        let context = InvocationContext {
+            invocation_id: Some("42".to_string()),
+            function_name: Some("WarningReport".to_string()),
+            ..InvocationContext::default()
+        };
+        let guard = context.enter();
+        log_all_levels(&logger);
+
+        assert_eq!(
+            info.take(),
+            ["[WarningReport 42] INFO\n[WarningReport 42] line"]
+        );
+        let error = error.take();
+        // The function name contains `warn`, so the error is transformed and the warning lines are not prefixed.
+        assert_eq!(
+            crate::decode::decode_line(&error[0]),
+            Some(Ok(
+                "[WarningReport 42] ERROR\n[WarningReport 42] line".to_string()
+            ))
+        );
+        assert_eq!(error[1], "[WarningReport 42] WARN\n[WarningReport 42] line");
+        drop(guard);
+        logger.log(&record(log::Level::Info));
+        assert_eq!(info.take(), [""]);
+    }
+
+    #[test]
+    fn context_fields() {
+        use crate::{InvocationContext, LogContext};
+        use log::Log;
+
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let logger = AfchLogger::builder()
+            .info_sink(info.clone())
+            .error_sink(error.clone())
+            .build();
+        let _invocation = InvocationContext {
+            function_name: Some("orders".to_string()),
+            ..InvocationContext::default()
+        }
+        .enter();
+        let _context = LogContext::new().with("warning_count", 0).enter();
+        logger.log(&record(log::Level::Info));
+        logger.log(&record(log::Level::Error));
+
+        assert_eq!(info.take(), ["[orders] {warning_count=0} "]);
+        // The key contains `warn`, so the error is transformed.
+        assert_eq!(
+            crate::decode::decode_line(&error.take()[0]),
+            Some(Ok("[orders] {warning_count=0} ".to_string()))
+        );
+    }
+
+    #[test]
+    fn collected_context_fields() {
+        use crate::{LogCollector, LogContext};
+        use log::Log;
+
+        let info = MemorySink::new();
+        let logger = AfchLogger::builder().info_sink(info.clone()).build();
+        let collector = LogCollector::new();
+        let _collector = collector.enter();
+        let _context = LogContext::new().with("tenant_id", "acme").enter();
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("handled"))
+                .build(),
+        );
+
+        assert_eq!(collector.take().0, ["INFO: {tenant_id=acme} handled"]);
+        assert!(info.take().is_empty());
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn key_values() {
+        use log::Log;
+
+        let error = MemorySink::new();
+        let kvs: &[(&str, u64)] = &[("warning_count", 2)];
+        let logger = AfchLogger::builder().error_sink(error.clone()).build();
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Error)
+                .args(format_args!("failed"))
+                .key_values(&kvs)
+                .build(),
+        );
+        // The key contains `warn`, so the error is transformed instead of being downgraded to a warning.
+        assert_eq!(
+            crate::decode::decode_line(&error.take()[0]),
+            Some(Ok("failed warning_count=2".to_string()))
+        );
+
+        let logger = AfchLogger::builder()
+            .error_sink(error.clone())
+            .kv_style(crate::KvStyle::Json)
+            .build();
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("slow"))
+                .key_values(&[("attempt", 3)])
+                .build(),
+        );
+        assert_eq!(error.take(), [r#"warning: slow {"attempt":3}"#]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Values entered on a thread or while a future is polled, shared by [LogCollector](crate::LogCollector),
+//! [InvocationContext](crate::InvocationContext) and [LogContext](crate::LogContext).
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread::LocalKey;
+
+/// A value that can be entered. Each type has its own thread-local slot holding the entered value.
+pub trait Scope: Clone + Unpin + 'static {
+    fn slot() -> &'static LocalKey<RefCell<Option<Self>>>;
+}
+
+/// Restores the previously entered value of the same type when dropped, see [CollectorGuard](crate::CollectorGuard),
+/// [InvocationGuard](crate::InvocationGuard) and [LogContextGuard](crate::LogContextGuard).
+pub struct ScopeGuard<T: Scope> {
+    previous: Option<T>,
+    // The guard changes the state of the thread it is created on.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T: Scope> Drop for ScopeGuard<T> {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        T::slot().with(|slot| *slot.borrow_mut() = previous);
+    }
+}
+
+/// A future with a value entered each time it is polled, so that the value follows the future across the
+/// threads of the executor, see [LogCollector::scope](crate::LogCollector::scope),
+/// [InvocationContext::scope](crate::InvocationContext::scope) and [LogContext::scope](crate::LogContext::scope).
+#[must_use = "futures do nothing unless polled"]
+pub struct Scoped<T: Scope, F> {
+    value: T,
+    future: Pin<Box<F>>,
+}
+
+impl<T: Scope, F: Future> Future for Scoped<T, F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let _guard = enter(self.value.clone());
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Makes `value` the entered value of its type on the current thread until the guard is dropped.
+pub(crate) fn enter<T: Scope>(value: T) -> ScopeGuard<T> {
+    let previous = T::slot().with(|slot| slot.borrow_mut().replace(value));
+    ScopeGuard {
+        previous,
+        _not_send: PhantomData,
+    }
+}
+
+/// Makes `value` the entered value of its type while `future` is polled.
+pub(crate) fn scope<T: Scope, F: Future>(value: T, future: F) -> Scoped<T, F> {
+    Scoped {
+        value,
+        future: Box::pin(future),
+    }
+}
+
+/// Calls `f` with the value of type `T` entered on the current thread, if any.
+pub(crate) fn with_current<T: Scope, R>(f: impl FnOnce(Option<&T>) -> R) -> R {
+    T::slot().with(|slot| f(slot.borrow().as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Tag(usize);
+
+    thread_local! {
+        static TAG: RefCell<Option<Tag>> = const { RefCell::new(None) };
+    }
+
+    impl Scope for Tag {
+        fn slot() -> &'static LocalKey<RefCell<Option<Self>>> {
+            &TAG
+        }
+    }
+
+    fn current() -> Option<Tag> {
+        with_current(|tag: Option<&Tag>| tag.cloned())
+    }
+
+    #[test]
+    fn nested_guards() {
+        assert_eq!(current(), None);
+        {
+            let _outer = enter(Tag(1));
+            {
+                let _inner = enter(Tag(2));
+                assert_eq!(current(), Some(Tag(2)));
+            }
+            assert_eq!(current(), Some(Tag(1)));
+        }
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn across_await_points() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_time()
+            .build()
+            .unwrap();
+        let tasks: Vec<_> = (0..8)
+            .map(|i| {
+                runtime.spawn(scope(Tag(i), async {
+                    let mut tags = Vec::new();
+                    for _ in 0..4 {
+                        tags.push(current());
+                        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                    }
+                    tags
+                }))
+            })
+            .collect();
+        for (i, task) in tasks.into_iter().enumerate() {
+            assert_eq!(runtime.block_on(task).unwrap(), vec![Some(Tag(i)); 4]);
+        }
+        // The values do not leak into the threads of the runtime.
+        let outside = runtime.block_on(runtime.spawn(async { current() }));
+        assert_eq!(outside.unwrap(), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Ordering the logs across stdout and stderr.
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The sequence number and time of a record, prefixed to each of its lines by
+/// [Builder::sequence_numbers](crate::Builder::sequence_numbers) as `[<sequence> <seconds>.<microseconds>] `,
+/// for example `[42 1760745600.123456] `.
+///
+/// The host reads stdout and stderr separately, so the exported logs of the two streams can be out of order.
+/// The sequence numbers are increasing in the order the records are logged, [sort_by_sequence] restores the
+/// order. The prefix contains only digits, spaces, brackets and a dot, so it never contains `warn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SequenceKey {
+    pub sequence: u64,
+    /// Microseconds since the Unix epoch when the record was logged.
+    pub timestamp_micros: u64,
+}
+
+impl SequenceKey {
+    pub(crate) fn next(counter: &AtomicU64) -> SequenceKey {
+        SequenceKey {
+            sequence: counter.fetch_add(1, Ordering::Relaxed),
+            timestamp_micros: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_micros() as u64),
+        }
+    }
+
+    /// Prefixes each line of `msg` by the key.
+    pub(crate) fn prefix_lines(&self, msg: &str) -> String {
+        crate::logger::prefix_lines(&format!("{} ", self), msg)
+    }
+
+    /// Splits the key prefixed to `line`, returning the key and the rest of the line.
+    pub fn parse(line: &str) -> Option<(SequenceKey, &str)> {
+        let (key, rest) = line.strip_prefix('[')?.split_once("] ")?;
+        let (sequence, timestamp) = key.split_once(' ')?;
+        let (secs, micros) = timestamp.split_once('.')?;
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !digits(sequence) || !digits(secs) || micros.len() != 6 || !digits(micros) {
+            return None;
+        }
+        let key = SequenceKey {
+            sequence: sequence.parse().ok()?,
+            timestamp_micros: secs
+                .parse::<u64>()
+                .ok()?
+                .checked_mul(1_000_000)?
+                .checked_add(micros.parse().ok()?)?,
+        };
+        Some((key, rest))
+    }
+}
+
+impl fmt::Display for SequenceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{} {}.{:06}]",
+            self.sequence,
+            self.timestamp_micros / 1_000_000,
+            self.timestamp_micros % 1_000_000
+        )
+    }
+}
+
+/// Sorts exported logs by their [SequenceKey]s, where `line` gives the logged line of an entry. The sequence
+/// numbers restart with the process, so the logs should come from a single run of a single instance.
+/// The sort is stable, so the lines of a record stay in order. The entries without a key are kept in front of
+/// the others.
+pub fn sort_by_sequence<T>(entries: &mut [T], line: impl Fn(&T) -> &str) {
+    entries.sort_by_cached_key(|entry| SequenceKey::parse(line(entry)).map(|(key, _)| key));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contains_warn;
+
+    #[test]
+    fn round_trip() {
+        let counter = AtomicU64::new(7);
+        let key = SequenceKey::next(&counter);
+        assert_eq!(key.sequence, 7);
+        assert_eq!(SequenceKey::next(&counter).sequence, 8);
+
+        let line = key.prefix_lines("first\nsecond");
+        let (first, second) = line.split_once('\n').unwrap();
+        assert_eq!(SequenceKey::parse(first), Some((key, "first")));
+        assert_eq!(SequenceKey::parse(second), Some((key, "second")));
+        assert!(!contains_warn(&key.to_string()));
+    }
+
+    #[test]
+    fn format() {
+        let key = SequenceKey {
+            sequence: 42,
+            timestamp_micros: 1_760_745_600_000_042,
+        };
+        assert_eq!(key.to_string(), "[42 1760745600.000042]");
+        assert_eq!(
+            SequenceKey::parse("[42 1760745600.000042] x"),
+            Some((key, "x"))
+        );
+        for invalid in [
+            "42 1.000000] x",
+            "[42 1.42] x",
+            "[42 1.000000]x",
+            "[a 1.000000] x",
+        ] {
+            assert_eq!(SequenceKey::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sort() {
+        let mut lines = vec![
+            "[2 1.000000] error",
+            "[0 1.000000] info",
+            "no key",
+            "[1 1.000000] info\nline",
+        ];
+        sort_by_sequence(&mut lines, |line| line);
+        assert_eq!(
+            lines,
+            [
+                "no key",
+                "[0 1.000000] info",
+                "[1 1.000000] info\nline",
+                "[2 1.000000] error"
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Shutting down the logger when the process is terminated.
+use std::io;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// Calls [shutdown](crate::shutdown) when the process receives SIGTERM or SIGINT, then terminates the
+/// process as the signal would have, so that the logs of the last moments, such as the error that
+/// caused the host to stop the handler, are written.
+///
+/// It spawns a thread waiting for the signals. The process is terminated right after the logs are
+/// written, cutting off any graceful shutdown the application starts on the same signal, for example
+/// by `tokio::signal`. Use [drain_on_signal] in that case.
+pub fn shutdown_on_signal() -> io::Result<()> {
+    on_signal(true)
+}
+
+/// Calls [shutdown](crate::shutdown) each time the process receives SIGTERM or SIGINT, leaving it to the
+/// application to terminate.
+///
+/// It spawns a thread waiting for the signals. Registering it replaces the default action of the signals,
+/// so the process is no longer terminated by them unless the application handles them too. The records
+/// logged afterwards are written directly.
+pub fn drain_on_signal() -> io::Result<()> {
+    on_signal(false)
+}
+
+fn on_signal(terminate: bool) -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    std::thread::Builder::new()
+        .name("afch-logger-signal".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                crate::shutdown();
+                if terminate {
+                    let _ = signal_hook::low_level::emulate_default_handler(signal);
+                }
+            }
+        })?;
+    Ok(())
+}
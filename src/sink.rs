@@ -0,0 +1,235 @@
+//! Where the logger writes its lines.
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A destination of log lines.
+///
+/// The logger has an information sink, stdout by default, and an error sink for warning and error logs,
+/// stderr by default. Remember that the runtime only infers the level of the lines written to the real
+/// stdout and stderr.
+pub trait Sink: Send + Sync {
+    /// Writes a line, which does not end with a newline.
+    fn write_line(&self, line: &str) -> io::Result<()>;
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes to the stdout of the process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        write_line_to(&mut io::stdout().lock(), line)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Writes to the stderr of the process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrSink;
+
+impl Sink for StderrSink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        write_line_to(&mut io::stderr().lock(), line)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Writes to any [Write], such as a file.
+#[derive(Debug, Default)]
+pub struct WriterSink<W>(Mutex<W>);
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> WriterSink<W> {
+        WriterSink(Mutex::new(writer))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.0
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn writer(&self) -> MutexGuard<'_, W> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<W: Write + Send> Sink for WriterSink<W> {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        write_line_to(&mut *self.writer(), line)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+/// Keeps the lines in memory, for tests. Every clone refers to the same lines.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink(Arc<Mutex<Vec<String>>>);
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+
+    /// The lines written so far.
+    pub fn lines(&self) -> Vec<String> {
+        self.guard().clone()
+    }
+
+    /// Takes the lines written so far, leaving the sink empty.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.guard())
+    }
+
+    fn guard(&self) -> MutexGuard<'_, Vec<String>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Sink for MemorySink {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        self.guard().push(line.to_string());
+        Ok(())
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Arc<S> {
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        (**self).write_line(line)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// What the logger does when a sink fails to write a line, for example because the host has closed
+/// the pipe. In any case the logger does not panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteErrorPolicy {
+    /// Drops the line.
+    #[default]
+    Drop,
+    /// Drops the line and increments the [WriteErrorCounter].
+    Count,
+    /// Writes the line again, and if it fails again, drops it and increments the [WriteErrorCounter].
+    /// Part of the line may be written twice.
+    RetryOnce,
+}
+
+impl WriteErrorPolicy {
+    pub(crate) fn write(
+        self,
+        sink: &dyn Sink,
+        line: &str,
+        counter: &WriteErrorCounter,
+    ) -> io::Result<()> {
+        let mut result = sink.write_line(line);
+        if result.is_err() && self == WriteErrorPolicy::RetryOnce {
+            result = sink.write_line(line);
+        }
+        if result.is_err() && self != WriteErrorPolicy::Drop {
+            counter.increment();
+        }
+        result
+    }
+}
+
+/// The number of lines the logger has failed to write. Every clone refers to the same number.
+#[derive(Debug, Clone, Default)]
+pub struct WriteErrorCounter(Arc<AtomicU64>);
+
+impl WriteErrorCounter {
+    pub fn new() -> WriteErrorCounter {
+        WriteErrorCounter::default()
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Writes the line and the newline at once, so that concurrent lines are not interleaved.
+fn write_line_to(writer: &mut impl Write, line: &str) -> io::Result<()> {
+    let mut buf = String::with_capacity(line.len() + 1);
+    buf.push_str(line);
+    buf.push('\n');
+    writer.write_all(buf.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writer_sink() {
+        let sink = WriterSink::new(Vec::new());
+        sink.write_line("first").unwrap();
+        sink.write_line("second\nline").unwrap();
+        sink.flush().unwrap();
+        assert_eq!(sink.into_inner(), b"first\nsecond\nline\n");
+    }
+
+    struct FailingSink {
+        failures: AtomicU64,
+        written: MemorySink,
+    }
+
+    impl Sink for FailingSink {
+        fn write_line(&self, line: &str) -> io::Result<()> {
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                Err(io::ErrorKind::BrokenPipe.into())
+            } else {
+                self.written.write_line(line)
+            }
+        }
+    }
+
+    #[test]
+    fn write_error_policy() {
+        let write = |policy: WriteErrorPolicy, failures| {
+            let sink = FailingSink {
+                failures: AtomicU64::new(failures),
+                written: MemorySink::new(),
+            };
+            let counter = WriteErrorCounter::new();
+            let result = policy.write(&sink, "line", &counter);
+            (result.is_ok(), counter.get(), sink.written.lines().len())
+        };
+        assert_eq!(write(WriteErrorPolicy::Drop, 1), (false, 0, 0));
+        assert_eq!(write(WriteErrorPolicy::Count, 1), (false, 1, 0));
+        assert_eq!(write(WriteErrorPolicy::RetryOnce, 1), (true, 0, 1));
+        assert_eq!(write(WriteErrorPolicy::RetryOnce, 2), (false, 1, 0));
+    }
+
+    #[test]
+    fn memory_sink() {
+        let sink = MemorySink::new();
+        sink.clone().write_line("line").unwrap();
+        assert_eq!(sink.lines(), ["line"]);
+        assert_eq!(sink.take(), ["line"]);
+        assert!(sink.lines().is_empty());
+    }
+}
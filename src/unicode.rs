@@ -0,0 +1,158 @@
+//! Breaking the `warn` occurrences in error logs by invisible or look-alike Unicode characters.
+use crate::{contains_warn, DefaultTransform, Transform};
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const CYRILLIC_SMALL_A: char = '\u{0430}';
+const CYRILLIC_CAPITAL_A: char = '\u{0410}';
+
+/// Breaks each `warn` (case insensitive) in the error logs so that they look unchanged to humans, while
+/// the warning logs are prefixed by [DefaultTransform].
+///
+/// It relies on the runtime comparing the characters one by one, ignoring only the ASCII case, which is
+/// what it appears to do. [UnicodeTransform::normalize] restores the logs when exporting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeTransform {
+    /// Inserts a zero width joiner (U+200D) after the `w`. To keep it reversible, a zero width joiner already
+    /// following a `w` gets another one inserted.
+    ZeroWidth,
+    /// Replaces the `a` by the Cyrillic `а` (U+0430), or `A` by `А` (U+0410). To keep it reversible, Cyrillic
+    /// `а`s or `А`s already between a `w` and `rn` get another one inserted.
+    Homoglyph,
+}
+
+impl UnicodeTransform {
+    /// Breaks each `warn` of `msg`.
+    pub fn apply(&self, msg: &str) -> String {
+        let mut result = String::with_capacity(msg.len() + 8);
+        let mut rest = msg;
+        while let Some(ch) = rest.chars().next() {
+            if let Some(warn) = rest.get(..4).filter(|head| contains_warn(head)) {
+                result.push_str(&warn[..1]);
+                match self {
+                    UnicodeTransform::ZeroWidth => {
+                        result.push(ZERO_WIDTH_JOINER);
+                        result.push_str(&warn[1..2]);
+                    }
+                    UnicodeTransform::Homoglyph if &warn[1..2] == "a" => {
+                        result.push(CYRILLIC_SMALL_A)
+                    }
+                    UnicodeTransform::Homoglyph => result.push(CYRILLIC_CAPITAL_A),
+                }
+                result.push_str(&warn[2..]);
+                rest = &rest[4..];
+                continue;
+            }
+
+            result.push(ch);
+            rest = &rest[ch.len_utf8()..];
+            if !ch.eq_ignore_ascii_case(&'w') {
+                continue;
+            }
+            match self {
+                UnicodeTransform::ZeroWidth if rest.starts_with(ZERO_WIDTH_JOINER) => {
+                    result.push(ZERO_WIDTH_JOINER)
+                }
+                UnicodeTransform::ZeroWidth => {}
+                UnicodeTransform::Homoglyph => {
+                    if let Some(first) = homoglyph_run(rest).and_then(|run| run.chars().next()) {
+                        result.push(first);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Restores the log broken by [UnicodeTransform::apply].
+    pub fn normalize(&self, s: &str) -> String {
+        let mut result = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(ch) = rest.chars().next() {
+            result.push(ch);
+            rest = &rest[ch.len_utf8()..];
+            if !ch.eq_ignore_ascii_case(&'w') {
+                continue;
+            }
+            match self {
+                UnicodeTransform::ZeroWidth => {
+                    rest = rest.strip_prefix(ZERO_WIDTH_JOINER).unwrap_or(rest);
+                }
+                UnicodeTransform::Homoglyph => {
+                    let Some(run) = homoglyph_run(rest) else {
+                        continue;
+                    };
+                    // A single one replaced the `a` of a `warn`, otherwise the first one was inserted.
+                    let first = run.chars().next().unwrap_or_default();
+                    if run.len() == first.len_utf8() {
+                        result.push(if first == CYRILLIC_SMALL_A { 'a' } else { 'A' });
+                    }
+                    rest = &rest[first.len_utf8()..];
+                }
+            }
+        }
+        result
+    }
+}
+
+// The Cyrillic `а`s and `А`s at the start of `s`, if there is any and they are followed by `rn`.
+fn homoglyph_run(s: &str) -> Option<&str> {
+    let len = s
+        .find(|c| c != CYRILLIC_SMALL_A && c != CYRILLIC_CAPITAL_A)
+        .unwrap_or(s.len());
+    let rn = s[len..].get(..2)?;
+    (len > 0 && rn.eq_ignore_ascii_case("rn")).then(|| &s[..len])
+}
+
+impl Transform for UnicodeTransform {
+    fn transform_error(&self, msg: String) -> String {
+        self.apply(&msg)
+    }
+
+    fn transform_warning(&self, msg: String) -> String {
+        DefaultTransform.transform_warning(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn examples() {
+        assert_eq!(
+            UnicodeTransform::ZeroWidth.apply("Warning"),
+            "W\u{200D}arning"
+        );
+        assert_eq!(
+            UnicodeTransform::Homoglyph.apply("warn WARN"),
+            "w\u{0430}rn W\u{0410}RN"
+        );
+        assert_eq!(
+            UnicodeTransform::ZeroWidth.apply("w\u{200D}x"),
+            "w\u{200D}\u{200D}x"
+        );
+        assert_eq!(
+            UnicodeTransform::Homoglyph.apply("w\u{0430}rn"),
+            "w\u{0430}\u{0430}rn"
+        );
+        assert_eq!(
+            UnicodeTransform::Homoglyph.normalize("w\u{0430}\u{0430}rn"),
+            "w\u{0430}rn"
+        );
+    }
+
+    #[test]
+    fn never_warn_and_round_trip() {
+        const ALPHABET: [char; 12] = [
+            'w', 'W', 'a', 'A', '\u{0430}', '\u{0410}', 'r', 'n', 'N', ' ', '\u{200D}', '界',
+        ];
+
+        for msg in crate::tests::random_strings(0x0123_4567_89ab_cdef, &ALPHABET, 24).take(20_000) {
+            for transform in [UnicodeTransform::ZeroWidth, UnicodeTransform::Homoglyph] {
+                let applied = transform.apply(&msg);
+                assert!(!contains_warn(&applied), "{:?}", msg);
+                assert_eq!(transform.normalize(&applied), msg);
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Routing the output of `tracing_subscriber::fmt` by the levels of the events.
+use std::io;
+use std::sync::Arc;
+
+use tracing::Metadata;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::AfchLogger;
+
+/// A [MakeWriter] for `tracing_subscriber::fmt`, writing each formatted event by an [AfchLogger] at the
+/// level of the event, so that it goes to the output of the level and warning and error events are
+/// transformed like the records of `log`, while the formatting stays up to `fmt`.
+///
+/// The levels and filters of the logger do not apply, the events are filtered by the subscriber. The other
+/// options, such as the transform, the sinks and the prefixes of debug and trace logs, do. Disable the ANSI
+/// colors of `fmt` by `with_ansi(false)`, as the escape codes are left in the logs.
+///
+/// As for [AfchLayer](crate::AfchLayer), the logger is shut down by [shutdown](crate::shutdown) while
+/// the make-writer is alive.
+///
+/// ```
+/// let subscriber = tracing_subscriber::fmt()
+///     .with_writer(afch_logger::AfchMakeWriter::default())
+///     .with_ansi(false)
+///     .finish();
+/// ```
+#[derive(Debug)]
+pub struct AfchMakeWriter {
+    logger: Arc<AfchLogger>,
+}
+
+impl AfchMakeWriter {
+    /// `logger` may be an [Arc] shared with other users, such as an [AfchLayer](crate::AfchLayer).
+    pub fn new(logger: impl Into<Arc<AfchLogger>>) -> AfchMakeWriter {
+        AfchMakeWriter {
+            logger: crate::logger::share(logger),
+        }
+    }
+
+    /// The logger the writers write to.
+    pub fn logger(&self) -> &Arc<AfchLogger> {
+        &self.logger
+    }
+}
+
+impl Default for AfchMakeWriter {
+    fn default() -> Self {
+        AfchMakeWriter::new(AfchLogger::new())
+    }
+}
+
+impl<'a> MakeWriter<'a> for AfchMakeWriter {
+    type Writer = AfchWriter<'a>;
+
+    /// Makes a writer at Info level, as the level is unknown.
+    fn make_writer(&'a self) -> Self::Writer {
+        AfchWriter {
+            logger: &self.logger,
+            level: log::Level::Info,
+            target: String::new(),
+            module_path: None,
+            file: None,
+            line: None,
+            buf: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        AfchWriter {
+            logger: &self.logger,
+            level: crate::layer::log_level(meta.level()),
+            target: meta.target().to_string(),
+            module_path: meta.module_path().map(str::to_string),
+            file: meta.file().map(str::to_string),
+            line: meta.line(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// The writer of [AfchMakeWriter]. It keeps the output of an event and writes it when dropped.
+pub struct AfchWriter<'a> {
+    logger: &'a AfchLogger,
+    level: log::Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    buf: Vec<u8>,
+}
+
+impl io::Write for AfchWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for AfchWriter<'_> {
+    fn drop(&mut self) {
+        let output = String::from_utf8_lossy(&self.buf);
+        let output = output.strip_suffix('\n').unwrap_or(&output);
+        if output.is_empty() {
+            return;
+        }
+        self.logger.write_record(
+            &log::Record::builder()
+                .level(self.level)
+                .target(&self.target)
+                .module_path(self.module_path.as_deref())
+                .file(self.file.as_deref())
+                .line(self.line)
+                .args(format_args!("{}", output))
+                .build(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemorySink;
+
+    #[test]
+    fn routing_and_transform() {
+        let info = MemorySink::new();
+        let error = MemorySink::new();
+        let make_writer = AfchMakeWriter::new(
+            AfchLogger::builder()
+                .info_sink(info.clone())
+                .error_sink(error.clone())
+                .build(),
+        );
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(make_writer)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .without_time()
+            .with_target(false)
+            .with_level(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("started");
+            tracing::debug!("detail");
+            tracing::warn!(attempt = 2, "slow");
+            tracing::error!("failed");
+            tracing::error!(warnings = 1, "failed");
+        });
+
+        assert_eq!(info.take(), ["started", "debug: detail"]);
+        let error = error.take();
+        assert_eq!(error[..2], ["warning: slow attempt=2", "failed"]);
+        assert_eq!(
+            crate::decode::decode_line(&error[2]),
+            Some(Ok("failed warnings=1".to_string()))
+        );
+    }
+}